@@ -0,0 +1,107 @@
+use serde_json::json;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Request without the admin token, answered with a 401 by `handle_rejection`
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Let the request through only with the header `Authorization: Bearer <admin_token>`.
+/// Without an admin token, nobody gets through.
+/// Put it after the path and the method of the route, so that the other routes are still tried
+pub fn admin_only(
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let admin_token = Arc::new(admin_token);
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let admin_token = Arc::clone(&admin_token);
+            async move {
+                if is_admin(admin_token.as_deref(), authorization.as_deref()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn is_admin(admin_token: Option<&str>, authorization: Option<&str>) -> bool {
+    let token = authorization.and_then(|value| value.strip_prefix("Bearer "));
+    match (admin_token, token) {
+        (Some(admin_token), Some(token)) => token == admin_token,
+        _ => false,
+    }
+}
+
+/// Turn the rejection of `admin_only` into a 401, the others keep the answer of warp
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unauthorized" })),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protected(
+        admin_token: Option<&str>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::path("kick")
+            .and(admin_only(admin_token.map(str::to_string)))
+            .map(|| "kicked")
+            .recover(handle_rejection)
+    }
+
+    async fn status(admin_token: Option<&str>, authorization: Option<&str>) -> u16 {
+        let mut request = warp::test::request().path("/kick");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request
+            .reply(&protected(admin_token))
+            .await
+            .status()
+            .as_u16()
+    }
+
+    #[tokio::test]
+    async fn missing_or_wrong_token_is_unauthorized() {
+        assert_eq!(status(Some("secret"), None).await, 401);
+        assert_eq!(status(Some("secret"), Some("Bearer guess")).await, 401);
+        assert_eq!(status(Some("secret"), Some("secret")).await, 401);
+        // Without an admin token, the routes stay closed
+        assert_eq!(status(None, Some("Bearer secret")).await, 401);
+    }
+
+    #[tokio::test]
+    async fn correct_token_reaches_the_handler() {
+        let response = warp::test::request()
+            .path("/kick")
+            .header("authorization", "Bearer secret")
+            .reply(&protected(Some("secret")))
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), "kicked");
+    }
+
+    #[tokio::test]
+    async fn other_routes_are_still_tried() {
+        let routes = warp::path("kick")
+            .and(admin_only(Some("secret".to_string())))
+            .map(|| "kicked")
+            .or(warp::path("kick").map(|| "public"));
+        let response = warp::test::request().path("/kick").reply(&routes).await;
+        assert_eq!(response.body(), "public");
+    }
+}
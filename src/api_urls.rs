@@ -0,0 +1,89 @@
+use serde::Serialize;
+use serde_json::json;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// How long the browsers and the proxies may keep the URLs, they only change with a restart
+const CACHE_MAX_AGE_SECS: u32 = 300;
+
+#[derive(Clone, Serialize)]
+struct ApiUrls {
+    backend_url: String,
+    websocket_url: String,
+}
+
+/// `GET /auth-api-url`, the body is serialized once here. Without a backend URL the
+/// clients get a 503 `backend_url_not_set`, which is not cached
+pub fn route(
+    backend_url: Option<String>,
+    websocket_url: String,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    let body = backend_url.map(|backend_url| {
+        serde_json::to_string(&ApiUrls {
+            backend_url,
+            websocket_url,
+        })
+        .expect("The URLs are always serializable")
+    });
+
+    warp::path("auth-api-url")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || match &body {
+            Some(body) => {
+                let reply =
+                    warp::reply::with_header(body.clone(), "content-type", "application/json");
+                let cache_control = format!("public, max-age={}", CACHE_MAX_AGE_SECS);
+                warp::reply::with_header(reply, "cache-control", cache_control).into_response()
+            }
+            None => warp::reply::with_header(
+                warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "backend_url_not_set"})),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                "cache-control",
+                "no-store",
+            )
+            .into_response(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEBSOCKET_URL: &str = "ws://127.0.0.1:3012/socket";
+
+    #[tokio::test]
+    async fn urls_are_served_with_a_cache_lifetime() {
+        let route = route(
+            Some("https://api.example.com".to_string()),
+            WEBSOCKET_URL.to_string(),
+        );
+        let response = warp::test::request()
+            .path("/auth-api-url")
+            .reply(&route)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["cache-control"], "public, max-age=300");
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["backend_url"], "https://api.example.com");
+        assert_eq!(body["websocket_url"], WEBSOCKET_URL);
+    }
+
+    #[tokio::test]
+    async fn unset_backend_url_is_unavailable() {
+        let route = route(None, WEBSOCKET_URL.to_string());
+        let response = warp::test::request()
+            .path("/auth-api-url")
+            .reply(&route)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "backend_url_not_set");
+    }
+}
@@ -0,0 +1,60 @@
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::Write;
+use warp::ws::Message;
+
+/// First byte of a compressed frame, the raw deflate stream of the frame follows
+pub const DEFLATE_MARKER: u8 = 0x01;
+/// Frames below this size are sent as they are, compressing them would not pay
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Deflate a frame above the threshold into a binary frame starting with `DEFLATE_MARKER`.
+/// Once inflated, it is the text (JSON) or the binary (MessagePack) frame it replaces
+pub fn compress(message: Message) -> Message {
+    let frame = message.as_bytes();
+    if frame.len() < COMPRESSION_THRESHOLD {
+        return message;
+    }
+
+    let mut encoder = DeflateEncoder::new(vec![DEFLATE_MARKER], Compression::fast());
+    match encoder.write_all(frame).and_then(|()| encoder.finish()) {
+        Ok(compressed) => Message::binary(compressed),
+        Err(e) => {
+            eprintln!("Failed to compress a frame: {}", e);
+            message
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn large_frame_inflates_back_to_the_json() {
+        let ships: Vec<serde_json::Value> = (0..50)
+            .map(|i| serde_json::json!({ "name": format!("ship {}", i), "position": [i, i, i] }))
+            .collect();
+        let json =
+            serde_json::json!({ "type": "state", "payload": { "ships": ships } }).to_string();
+        assert!(json.len() >= COMPRESSION_THRESHOLD);
+
+        let compressed = compress(Message::text(json.clone()));
+        assert!(compressed.is_binary());
+        let frame = compressed.as_bytes();
+        assert_eq!(frame[0], DEFLATE_MARKER);
+        let mut inflated = String::new();
+        DeflateDecoder::new(&frame[1..])
+            .read_to_string(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, json);
+    }
+
+    #[test]
+    fn small_frame_is_sent_as_it_is() {
+        let message = compress(Message::text(r#"{"type":"shutdown"}"#));
+        assert_eq!(message.to_str(), Ok(r#"{"type":"shutdown"}"#));
+    }
+}
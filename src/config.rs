@@ -0,0 +1,634 @@
+use crate::cors;
+use crate::kafka_encoder::KafkaFormat;
+use crate::kafka_producer::{KafkaConfig, KafkaKeyStrategy, KafkaMode};
+use crate::ship::{
+    Integrator, ShipSettings, SurfaceCollision, WorldBoundary, DEFAULT_GRAVITY_SOFTENING,
+    DEFAULT_MAX_SPEED, DEFAULT_SPOOL_TIME, DEFAULT_TRAIL_LENGTH,
+};
+use crate::solar_system::{
+    PlanetMotion, DEFAULT_GRAVITATIONAL_CONSTANT, DEFAULT_SUN_MASS, DEFAULT_WORLD_RADIUS,
+};
+use crate::vec3::VectorFormat;
+use std::env;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+const DEFAULT_WEBSOCKET_URL: &str = "ws://127.0.0.1:3012/socket";
+pub const DEFAULT_TICK_HZ: u32 = 30;
+/// Highest accepted `TICK_HZ` and `BROADCAST_HZ`
+const MAX_TICK_HZ: u32 = 240;
+const DEFAULT_MAX_COMMANDS_PER_SECOND: u32 = 120;
+const DEFAULT_KEYFRAME_INTERVAL: u32 = 30;
+const DEFAULT_PING_INTERVAL_SECS: u64 = 5;
+const DEFAULT_PONG_TIMEOUT_SECS: u64 = 15;
+/// Seconds a disconnected ship is kept for its client to reconnect
+const DEFAULT_SESSION_TTL_SECS: u64 = 30;
+const DEFAULT_KAFKA_MAX_RETRIES: u32 = 3;
+/// Wait before the first retry of a failed Kafka send, doubled at every retry
+const DEFAULT_KAFKA_RETRY_BASE_DELAY_MS: u64 = 100;
+
+/// Everything read from the environment, once at startup
+pub struct Config {
+    pub strict: bool,
+    pub websocket_url: String, // given to the clients by `/auth-api-url`
+    pub backend_url: Option<String>, // `/auth-api-url` answers a 503 when it is not set
+    pub websocket_host: String,
+    pub websocket_port: u16,
+    pub http_host: String, // of the HTTP API
+    pub http_port: u16,
+    pub single_port: bool, // the HTTP API under `/api` and the socket on `http_port`
+    pub admin_token: Option<String>, // the admin routes are closed when it is not set
+    pub allowed_origins: Option<Vec<String>>, // of the HTTP API, any origin when not set
+    pub tick_hz: u32,
+    pub broadcast_hz: u32, // a divisor of `tick_hz`
+    pub gravitational_constant: f64,
+    pub gravity_softening: f64,
+    pub sun_mass: f64,
+    pub planet_motion: PlanetMotion,
+    pub simulation_seed: Option<u64>,
+    pub planets_config: Option<String>, // path of a JSON file replacing the default planets
+    pub surface_collision: SurfaceCollision,
+    pub world_radius: f64,
+    pub world_boundary: WorldBoundary,
+    pub world_scale: f64, // of the planets, the spawn point and the world radius
+    pub ship: ShipSettings,
+    pub max_players: Option<usize>, // no limit when not set
+    pub max_commands_per_second: u32,
+    pub chat_range: Option<f64>, // chat heard by the whole room when not set
+    pub keyframe_interval: u32,
+    pub session_ttl: Duration,
+    pub ping_interval: Duration,
+    pub pong_timeout: Duration,
+    pub vector_format: VectorFormat, // of the WebSocket frames, the HTTP API and Kafka
+    pub kafka: KafkaConfig,
+    pub kafka_ship_positions: bool,
+    pub kafka_world_snapshots: bool,
+    pub record_path: Option<String>, // file the snapshots of every tick are written to
+    pub replay_path: Option<String>, // recording played instead of the simulation
+    pub replay_loop: bool,
+}
+
+/// A variable refused by the strict mode
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid { name: &'static str, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(name) => write!(f, "{} is not set", name),
+            ConfigError::Invalid { name, value } => {
+                write!(f, "{} has an invalid value: {}", name, value)
+            }
+        }
+    }
+}
+
+impl Config {
+    /// With `STRICT_CONFIG=true`, the essential variables must be set and every variable set
+    /// must be valid. Otherwise the defaults replace what is missing or invalid
+    pub fn from_env() -> Result<Self, Vec<ConfigError>> {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Same as `from_env`, reading the variables with `lookup`
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, Vec<ConfigError>> {
+        let strict = lookup("STRICT_CONFIG").is_some_and(|value| value == "true");
+        let mut vars = Variables {
+            lookup,
+            strict,
+            errors: Vec::new(),
+        };
+
+        let tick_hz = vars.number("TICK_HZ", DEFAULT_TICK_HZ, |value| {
+            (1..=MAX_TICK_HZ).contains(value)
+        });
+        let kafka_enabled = vars.number("KAFKA_ENABLED", true, |_| true);
+        let config = Config {
+            strict,
+            websocket_url: vars.required("WEBSOCKET_URL", DEFAULT_WEBSOCKET_URL),
+            backend_url: vars.essential("BACKEND_URL"),
+            websocket_host: vars.string("WEBSOCKET_HOST", "127.0.0.1"),
+            websocket_port: vars.number("WEBSOCKET_PORT", 3012, |_| true),
+            http_host: vars.string("HTTP_HOST", "127.0.0.1"),
+            http_port: vars.number("HTTP_PORT", 3030, |_| true),
+            single_port: vars.number("SINGLE_PORT", false, |_| true),
+            admin_token: vars.optional("ADMIN_TOKEN", |value| {
+                Some(value.to_string()).filter(|value| !value.is_empty())
+            }),
+            allowed_origins: vars.origins("ALLOWED_ORIGINS"),
+            tick_hz,
+            // A frame every whole number of ticks, the clients get them at a steady pace
+            broadcast_hz: vars.number("BROADCAST_HZ", tick_hz, |value| {
+                (1..=tick_hz).contains(value) && tick_hz % value == 0
+            }),
+            gravitational_constant: vars.number(
+                "GRAVITATIONAL_CONSTANT",
+                DEFAULT_GRAVITATIONAL_CONSTANT,
+                |&value| value.is_finite() && value > 0.0,
+            ),
+            gravity_softening: vars.number(
+                "GRAVITY_SOFTENING",
+                DEFAULT_GRAVITY_SOFTENING,
+                |&value| value.is_finite() && value >= 0.0,
+            ),
+            sun_mass: vars.number("SUN_MASS", DEFAULT_SUN_MASS, |&value| {
+                value.is_finite() && value >= 0.0
+            }),
+            planet_motion: vars.parsed("PLANET_MOTION", PlanetMotion::Rails, PlanetMotion::parse),
+            simulation_seed: vars.optional("SIMULATION_SEED", |value| value.parse().ok()),
+            planets_config: vars.optional("PLANETS_CONFIG", |value| Some(value.to_string())),
+            surface_collision: vars.parsed(
+                "PLANET_COLLISION",
+                SurfaceCollision::Stop,
+                SurfaceCollision::parse,
+            ),
+            world_radius: vars.number("WORLD_RADIUS", DEFAULT_WORLD_RADIUS, |&value| {
+                value.is_finite() && value > 0.0
+            }),
+            world_boundary: vars.parsed(
+                "WORLD_BOUNDARY",
+                WorldBoundary::Repel,
+                WorldBoundary::parse,
+            ),
+            world_scale: vars.number("WORLD_SCALE", 1.0, |&value| {
+                value.is_finite() && value > 0.0
+            }),
+            ship: ShipSettings {
+                spool_time: vars.number("SHIP_SPOOL_TIME", DEFAULT_SPOOL_TIME, |&value| {
+                    value.is_finite() && value >= 0.0
+                }),
+                max_speed: vars.number("SHIP_MAX_SPEED", DEFAULT_MAX_SPEED, |&value| {
+                    value.is_finite() && value > 0.0
+                }),
+                trail_length: vars.number("SHIP_TRAIL_LENGTH", DEFAULT_TRAIL_LENGTH, |_| true),
+                integrator: vars.parsed("SHIP_INTEGRATOR", Integrator::Euler, Integrator::parse),
+            },
+            max_players: vars.optional("MAX_PLAYERS", |value| {
+                value.parse().ok().filter(|&value| value > 0)
+            }),
+            max_commands_per_second: vars.number(
+                "MAX_COMMANDS_PER_SECOND",
+                DEFAULT_MAX_COMMANDS_PER_SECOND,
+                |&value| value > 0,
+            ),
+            chat_range: vars.optional("CHAT_RANGE", |value| {
+                value.parse().ok().filter(|value: &f64| *value > 0.0)
+            }),
+            keyframe_interval: vars.number(
+                "KEYFRAME_INTERVAL",
+                DEFAULT_KEYFRAME_INTERVAL,
+                |&value| value > 0,
+            ),
+            session_ttl: Duration::from_secs(vars.number(
+                "SESSION_TTL_SECS",
+                DEFAULT_SESSION_TTL_SECS,
+                |_| true,
+            )),
+            ping_interval: Duration::from_secs(vars.number(
+                "PING_INTERVAL_SECS",
+                DEFAULT_PING_INTERVAL_SECS,
+                |&value| value > 0,
+            )),
+            pong_timeout: Duration::from_secs(vars.number(
+                "PONG_TIMEOUT_SECS",
+                DEFAULT_PONG_TIMEOUT_SECS,
+                |&value| value > 0,
+            )),
+            vector_format: vars.parsed("VECTOR_FORMAT", VectorFormat::Array, VectorFormat::parse),
+            kafka: KafkaConfig {
+                enabled: kafka_enabled,
+                // Only essential to the servers which send to Kafka
+                brokers: if kafka_enabled {
+                    vars.required("KAFKA_BROKERS", "localhost:9092")
+                } else {
+                    vars.string("KAFKA_BROKERS", "localhost:9092")
+                },
+                topic: vars.string("KAFKA_TOPIC", "planet-positions"),
+                ship_topic: vars.string("KAFKA_SHIP_TOPIC", "ship-positions"),
+                world_topic: vars.string("KAFKA_WORLD_TOPIC", "world-snapshots"),
+                mode: vars.parsed("KAFKA_MODE", KafkaMode::Batch, KafkaMode::parse),
+                format: vars.parsed("KAFKA_FORMAT", KafkaFormat::Json, KafkaFormat::parse),
+                key_strategy: vars.parsed(
+                    "KAFKA_KEY_STRATEGY",
+                    KafkaKeyStrategy::Name,
+                    KafkaKeyStrategy::parse,
+                ),
+                max_retries: vars.number("KAFKA_MAX_RETRIES", DEFAULT_KAFKA_MAX_RETRIES, |_| true),
+                retry_base_delay: Duration::from_millis(vars.number(
+                    "KAFKA_RETRY_BASE_DELAY_MS",
+                    DEFAULT_KAFKA_RETRY_BASE_DELAY_MS,
+                    |_| true,
+                )),
+            },
+            kafka_ship_positions: vars.number("KAFKA_SHIP_POSITIONS", true, |_| true),
+            kafka_world_snapshots: vars.number("KAFKA_WORLD_SNAPSHOTS", false, |_| true),
+            record_path: vars.optional("RECORD_PATH", |value| Some(value.to_string())),
+            replay_path: vars.optional("REPLAY_PATH", |value| Some(value.to_string())),
+            replay_loop: vars.number("REPLAY_LOOP", false, |_| true),
+        };
+
+        if vars.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(vars.errors)
+        }
+    }
+
+    /// Ticks between two frames sent to a client
+    pub fn broadcast_stride(&self) -> u64 {
+        (self.tick_hz / self.broadcast_hz) as u64
+    }
+
+    /// Where the HTTP API listens
+    pub fn http_address(&self) -> Result<SocketAddr, String> {
+        socket_address("HTTP_HOST", "HTTP_PORT", &self.http_host, self.http_port)
+    }
+
+    /// Where the game socket listens
+    pub fn websocket_address(&self) -> Result<SocketAddr, String> {
+        socket_address(
+            "WEBSOCKET_HOST",
+            "WEBSOCKET_PORT",
+            &self.websocket_host,
+            self.websocket_port,
+        )
+    }
+
+    /// Print every resolved value at startup
+    pub fn log(&self) {
+        println!("STRICT_CONFIG: {}", self.strict);
+        println!("WEBSOCKET_URL: {}", self.websocket_url);
+        match &self.backend_url {
+            Some(backend_url) => println!("BACKEND_URL: {}", backend_url),
+            None => println!("BACKEND_URL not set, /auth-api-url answers a 503"),
+        }
+        // The token itself is a secret
+        println!("ADMIN_TOKEN set: {}", self.admin_token.is_some());
+        match &self.allowed_origins {
+            Some(origins) => println!("ALLOWED_ORIGINS: {}", origins.join(", ")),
+            None => println!("ALLOWED_ORIGINS not set, the HTTP API is open to any origin"),
+        }
+        println!(
+            "WEBSOCKET_HOST: {}, WEBSOCKET_PORT: {}",
+            self.websocket_host, self.websocket_port
+        );
+        println!(
+            "HTTP_HOST: {}, HTTP_PORT: {}, SINGLE_PORT: {}",
+            self.http_host, self.http_port, self.single_port
+        );
+        println!(
+            "TICK_HZ: {}, BROADCAST_HZ: {} (a frame every {} ticks)",
+            self.tick_hz,
+            self.broadcast_hz,
+            self.broadcast_stride()
+        );
+        println!(
+            "GRAVITATIONAL_CONSTANT: {}, GRAVITY_SOFTENING: {}, SUN_MASS: {}",
+            self.gravitational_constant, self.gravity_softening, self.sun_mass
+        );
+        println!("PLANET_MOTION: {:?}", self.planet_motion);
+        if let Some(seed) = self.simulation_seed {
+            println!("SIMULATION_SEED: {}", seed);
+        }
+        if let Some(path) = &self.planets_config {
+            println!("PLANETS_CONFIG: {}", path);
+        }
+        println!("PLANET_COLLISION: {:?}", self.surface_collision);
+        println!(
+            "SHIP_SPOOL_TIME: {}, SHIP_MAX_SPEED: {}, SHIP_TRAIL_LENGTH: {}, SHIP_INTEGRATOR: {:?}",
+            self.ship.spool_time, self.ship.max_speed, self.ship.trail_length, self.ship.integrator
+        );
+        println!(
+            "WORLD_RADIUS: {}, WORLD_BOUNDARY: {:?}, WORLD_SCALE: {}",
+            self.world_radius, self.world_boundary, self.world_scale
+        );
+        if let Some(max_players) = self.max_players {
+            println!("MAX_PLAYERS: {}", max_players);
+        }
+        println!(
+            "MAX_COMMANDS_PER_SECOND: {}, KEYFRAME_INTERVAL: {}, SESSION_TTL_SECS: {}",
+            self.max_commands_per_second,
+            self.keyframe_interval,
+            self.session_ttl.as_secs()
+        );
+        if let Some(chat_range) = self.chat_range {
+            println!("CHAT_RANGE: {}", chat_range);
+        }
+        println!(
+            "PING_INTERVAL_SECS: {}, PONG_TIMEOUT_SECS: {}",
+            self.ping_interval.as_secs(),
+            self.pong_timeout.as_secs()
+        );
+        println!("VECTOR_FORMAT: {:?}", self.vector_format);
+        println!("KAFKA_ENABLED: {}", self.kafka.enabled);
+        println!(
+            "KAFKA_BROKERS: {}, KAFKA_TOPIC: {}, KAFKA_SHIP_TOPIC: {}, KAFKA_WORLD_TOPIC: {}",
+            self.kafka.brokers, self.kafka.topic, self.kafka.ship_topic, self.kafka.world_topic
+        );
+        println!(
+            "KAFKA_SHIP_POSITIONS: {}, KAFKA_WORLD_SNAPSHOTS: {}",
+            self.kafka_ship_positions, self.kafka_world_snapshots
+        );
+        if let Some(path) = &self.record_path {
+            println!("RECORD_PATH: {}", path);
+        }
+        if let Some(path) = &self.replay_path {
+            println!("REPLAY_PATH: {}, REPLAY_LOOP: {}", path, self.replay_loop);
+        }
+        println!(
+            "KAFKA_MODE: {:?}, KAFKA_FORMAT: {:?}, KAFKA_KEY_STRATEGY: {:?}",
+            self.kafka.mode, self.kafka.format, self.kafka.key_strategy
+        );
+        println!(
+            "KAFKA_MAX_RETRIES: {}, KAFKA_RETRY_BASE_DELAY_MS: {}",
+            self.kafka.max_retries,
+            self.kafka.retry_base_delay.as_millis()
+        );
+    }
+}
+
+/// The first address `host` resolves to, the error names the variables to fix
+fn socket_address(
+    host_name: &str,
+    port_name: &str,
+    host: &str,
+    port: u16,
+) -> Result<SocketAddr, String> {
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| format!("Invalid {} or {}: {}:{}", host_name, port_name, host, port))
+}
+
+/// Reads the variables one by one, keeping what the strict mode refuses
+struct Variables<F> {
+    lookup: F,
+    strict: bool,
+    errors: Vec<ConfigError>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Variables<F> {
+    fn string(&mut self, name: &'static str, default: &str) -> String {
+        (self.lookup)(name).unwrap_or_else(|| default.to_string())
+    }
+
+    /// Essential variable, it must be set in strict mode
+    fn required(&mut self, name: &'static str, default: &str) -> String {
+        self.essential(name).unwrap_or_else(|| default.to_string())
+    }
+
+    /// Essential variable without a default
+    fn essential(&mut self, name: &'static str) -> Option<String> {
+        let value = (self.lookup)(name);
+        if value.is_none() && self.strict {
+            self.errors.push(ConfigError::Missing(name));
+        }
+        value
+    }
+
+    /// Comma-separated origins, they must be set in strict mode.
+    /// `None` opens the HTTP API to any origin, for the development only
+    fn origins(&mut self, name: &'static str) -> Option<Vec<String>> {
+        self.essential(name)?;
+        self.optional(name, |value| {
+            let origins: Vec<String> = value
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .collect();
+            origins
+                .iter()
+                .all(|origin| cors::is_valid_origin(origin))
+                .then_some(origins)
+        })
+    }
+
+    fn optional<T>(&mut self, name: &'static str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+        let value = (self.lookup)(name)?;
+        let parsed = parse(&value);
+        if parsed.is_none() {
+            self.invalid(name, value);
+        }
+        parsed
+    }
+
+    fn parsed<T>(
+        &mut self,
+        name: &'static str,
+        default: T,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> T {
+        self.optional(name, parse).unwrap_or(default)
+    }
+
+    /// A number (or a boolean) which must also pass `valid`
+    fn number<T: std::str::FromStr>(
+        &mut self,
+        name: &'static str,
+        default: T,
+        valid: impl Fn(&T) -> bool,
+    ) -> T {
+        self.parsed(name, default, |value| {
+            value.parse().ok().filter(|value| valid(value))
+        })
+    }
+
+    fn invalid(&mut self, name: &'static str, value: String) {
+        if self.strict {
+            self.errors.push(ConfigError::Invalid { name, value });
+        } else {
+            eprintln!("Ignoring {}, invalid value: {}", name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, Vec<ConfigError>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn variables_set_are_read_into_the_config() {
+        let config = config(&[
+            ("WEBSOCKET_PORT", "4000"),
+            ("TICK_HZ", "60"),
+            ("BROADCAST_HZ", "20"),
+            ("PLANET_COLLISION", "bounce"),
+            ("SHIP_MAX_SPEED", "25.5"),
+            ("KAFKA_TOPIC", "positions"),
+            ("KAFKA_MODE", "per_body"),
+            ("KAFKA_WORLD_SNAPSHOTS", "true"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.websocket_port, 4000);
+        assert_eq!(config.tick_hz, 60);
+        assert_eq!(config.broadcast_hz, 20);
+        assert_eq!(config.broadcast_stride(), 3);
+        assert_eq!(config.surface_collision, SurfaceCollision::Bounce);
+        assert_eq!(config.ship.max_speed, 25.5);
+        assert_eq!(config.ship.spool_time, DEFAULT_SPOOL_TIME);
+        assert_eq!(config.kafka.topic, "positions");
+        assert_eq!(config.kafka.mode, KafkaMode::PerBody);
+        assert!(config.kafka_world_snapshots);
+        assert_eq!(config.websocket_host, "127.0.0.1");
+        assert_eq!(
+            config.session_ttl,
+            Duration::from_secs(DEFAULT_SESSION_TTL_SECS)
+        );
+    }
+
+    #[test]
+    fn strict_mode_refuses_a_missing_variable() {
+        let errors = config(&[
+            ("STRICT_CONFIG", "true"),
+            ("WEBSOCKET_URL", "ws://example.com/socket"),
+            ("BACKEND_URL", "https://example.com"),
+        ])
+        .err()
+        .unwrap();
+        assert!(matches!(
+            errors[..],
+            [
+                ConfigError::Missing("ALLOWED_ORIGINS"),
+                ConfigError::Missing("KAFKA_BROKERS")
+            ]
+        ));
+
+        let config = config(&[("WEBSOCKET_URL", "ws://example.com/socket")]).unwrap();
+        assert_eq!(config.kafka.brokers, "localhost:9092");
+        assert_eq!(config.backend_url, None);
+    }
+
+    #[test]
+    fn invalid_rates_fall_back_to_the_default() {
+        let tick_hz = |value: &str| config(&[("TICK_HZ", value)]).unwrap().tick_hz;
+
+        assert_eq!(tick_hz("60"), 60);
+        assert_eq!(tick_hz("0"), DEFAULT_TICK_HZ);
+        assert_eq!(tick_hz("-5"), DEFAULT_TICK_HZ);
+        assert_eq!(tick_hz("10000"), DEFAULT_TICK_HZ);
+        assert_eq!(tick_hz("fast"), DEFAULT_TICK_HZ);
+        assert_eq!(config(&[]).unwrap().tick_hz, DEFAULT_TICK_HZ);
+
+        let errors = config(&[("STRICT_CONFIG", "true"), ("TICK_HZ", "0")])
+            .err()
+            .unwrap();
+        assert!(errors.iter().any(|error| matches!(
+            error,
+            ConfigError::Invalid {
+                name: "TICK_HZ",
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn http_address_is_resolved_from_host_and_port() {
+        let config = config(&[("HTTP_HOST", "0.0.0.0"), ("HTTP_PORT", "8080")]).unwrap();
+        assert_eq!(
+            config.http_address(),
+            Ok(SocketAddr::from(([0, 0, 0, 0], 8080)))
+        );
+    }
+
+    #[test]
+    fn invalid_http_address_names_its_variables() {
+        let address = config(&[("HTTP_HOST", "not a host"), ("HTTP_PORT", "8080")])
+            .unwrap()
+            .http_address();
+        assert_eq!(
+            address,
+            Err("Invalid HTTP_HOST or HTTP_PORT: not a host:8080".to_string())
+        );
+
+        let errors = config(&[("STRICT_CONFIG", "true"), ("HTTP_PORT", "70000")])
+            .err()
+            .expect("HTTP_PORT=70000 accepted");
+        assert!(errors
+            .iter()
+            .any(|error| error.to_string() == "HTTP_PORT has an invalid value: 70000"));
+    }
+
+    #[test]
+    fn broadcast_rate_must_divide_the_tick_rate() {
+        let stride = |tick_hz: &str, broadcast_hz: &str| {
+            config(&[("TICK_HZ", tick_hz), ("BROADCAST_HZ", broadcast_hz)])
+                .unwrap()
+                .broadcast_stride()
+        };
+
+        assert_eq!(stride("60", "20"), 3);
+        assert_eq!(stride("60", "60"), 1);
+        // Every 1.5 ticks cannot be sent, every tick instead
+        assert_eq!(stride("60", "40"), 1);
+        assert_eq!(stride("30", "60"), 1);
+
+        let errors = config(&[
+            ("STRICT_CONFIG", "true"),
+            ("TICK_HZ", "60"),
+            ("BROADCAST_HZ", "40"),
+        ])
+        .err()
+        .unwrap();
+        assert!(errors
+            .iter()
+            .any(|error| error.to_string() == "BROADCAST_HZ has an invalid value: 40"));
+    }
+
+    #[test]
+    fn gravity_must_be_finite() {
+        for value in ["inf", "-inf", "NaN", "-1"] {
+            let config = config(&[("GRAVITATIONAL_CONSTANT", value), ("SUN_MASS", value)]).unwrap();
+            assert_eq!(
+                config.gravitational_constant,
+                DEFAULT_GRAVITATIONAL_CONSTANT
+            );
+            assert_eq!(config.sun_mass, DEFAULT_SUN_MASS);
+        }
+
+        // No gravity at all is a zero sun mass, not a zero constant
+        let zero = config(&[("GRAVITATIONAL_CONSTANT", "0")]).unwrap();
+        assert_eq!(zero.gravitational_constant, DEFAULT_GRAVITATIONAL_CONSTANT);
+
+        let config = config(&[("GRAVITATIONAL_CONSTANT", "2.5"), ("SUN_MASS", "0")]).unwrap();
+        assert_eq!(config.gravitational_constant, 2.5);
+        assert_eq!(config.sun_mass, 0.0);
+    }
+
+    #[test]
+    fn world_and_ship_sizes_must_be_finite() {
+        for value in ["inf", "NaN"] {
+            let config = config(&[
+                ("WORLD_RADIUS", value),
+                ("SHIP_SPOOL_TIME", value),
+                ("SHIP_MAX_SPEED", value),
+            ])
+            .unwrap();
+            assert_eq!(config.world_radius, DEFAULT_WORLD_RADIUS);
+            assert_eq!(config.ship.spool_time, DEFAULT_SPOOL_TIME);
+            assert_eq!(config.ship.max_speed, DEFAULT_MAX_SPEED);
+        }
+    }
+
+    #[test]
+    fn replay_loops_with_true() {
+        let replay_loop = |value: &str| config(&[("REPLAY_LOOP", value)]).unwrap().replay_loop;
+
+        assert!(replay_loop("true"));
+        assert!(!replay_loop("false"));
+        assert!(!config(&[]).unwrap().replay_loop);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::messages::ServerMessage;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// What the server can ask of one connection, besides sending the snapshots
+#[derive(Debug)]
+pub enum Directive {
+    /// Frame for this client only, sent in the order of the directives
+    Message(ServerMessage),
+    /// Close the socket, after the frames asked before
+    Close,
+}
+
+/// The open connections by ship uuid, to reach one client
+#[derive(Default)]
+pub struct Connections {
+    senders: Mutex<HashMap<Uuid, mpsc::UnboundedSender<Directive>>>,
+}
+
+impl Connections {
+    /// The connection of the ship reads its directives from the receiver.
+    /// A previous connection of the same ship loses its receiver and closes
+    pub fn register(&self, ship_uuid: Uuid) -> mpsc::UnboundedReceiver<Directive> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.senders.lock().insert(ship_uuid, sender);
+        receiver
+    }
+
+    /// Once the receiver of the connection is dropped. The ship may have registered
+    /// a new connection in the meantime, it is kept
+    pub fn unregister(&self, ship_uuid: Uuid) {
+        let mut senders = self.senders.lock();
+        if senders
+            .get(&ship_uuid)
+            .is_some_and(|sender| sender.is_closed())
+        {
+            senders.remove(&ship_uuid);
+        }
+    }
+
+    /// False when the ship has no open connection
+    pub fn send(&self, ship_uuid: Uuid, directive: Directive) -> bool {
+        let mut senders = self.senders.lock();
+        match senders.get(&ship_uuid) {
+            Some(sender) if sender.send(directive).is_ok() => true,
+            Some(_) => {
+                // The connection closed without unregistering yet
+                senders.remove(&ship_uuid);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn send_message(&self, ship_uuid: Uuid, message: ServerMessage) -> bool {
+        self.send(ship_uuid, Directive::Message(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_reaches_only_the_intended_connection() {
+        let connections = Connections::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut first_receiver = connections.register(first);
+        let mut second_receiver = connections.register(second);
+
+        assert!(connections.send_message(first, ServerMessage::Kicked));
+        assert!(matches!(
+            first_receiver.try_recv(),
+            Ok(Directive::Message(ServerMessage::Kicked))
+        ));
+        assert!(second_receiver.try_recv().is_err());
+        assert!(!connections.send_message(Uuid::new_v4(), ServerMessage::Kicked));
+    }
+
+    #[test]
+    fn closed_connections_are_unregistered() {
+        let connections = Connections::default();
+        let uuid = Uuid::new_v4();
+        let receiver = connections.register(uuid);
+
+        // Still open, the entry stays
+        connections.unregister(uuid);
+        assert!(connections.send(uuid, Directive::Close));
+
+        drop(receiver);
+        connections.unregister(uuid);
+        assert!(connections.senders.lock().is_empty());
+
+        // Dropped without unregistering, the first send clears it
+        drop(connections.register(uuid));
+        assert!(!connections.send(uuid, Directive::Close));
+        assert!(connections.senders.lock().is_empty());
+    }
+
+    #[test]
+    fn reconnection_keeps_the_new_connection() {
+        let connections = Connections::default();
+        let uuid = Uuid::new_v4();
+        let old = connections.register(uuid);
+        let mut new = connections.register(uuid);
+
+        drop(old);
+        connections.unregister(uuid);
+        assert!(connections.send(uuid, Directive::Close));
+        assert!(matches!(new.try_recv(), Ok(Directive::Close)));
+    }
+}
@@ -0,0 +1,72 @@
+use warp::http::Uri;
+
+/// The CORS of the HTTP API, for `allowed_origins` only. Any origin when there is no list
+pub fn cors(allowed_origins: Option<&[String]>) -> warp::cors::Builder {
+    let cors = warp::cors()
+        .allow_header("content-type")
+        .allow_methods(["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
+    match allowed_origins {
+        Some(origins) => cors.allow_origins(origins.iter().map(String::as_str)),
+        None => cors.allow_any_origin(),
+    }
+}
+
+/// A scheme and a host, with an optional port, e.g. `https://game.example.com:8443`.
+/// Anything else would make `cors` panic
+pub fn is_valid_origin(origin: &str) -> bool {
+    let Ok(uri) = origin.parse::<Uri>() else {
+        return false;
+    };
+    uri.scheme().is_some()
+        && uri.authority().is_some()
+        && uri.path_and_query().is_none_or(|path| path == "/")
+        && !origin.ends_with('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    fn route() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let allowed = vec!["https://game.example.com".to_string()];
+        warp::path("ships").map(|| "[]").with(cors(Some(&allowed)))
+    }
+
+    #[tokio::test]
+    async fn allowed_origin_passes_through() {
+        let response = warp::test::request()
+            .path("/ships")
+            .header("origin", "https://game.example.com")
+            .reply(&route())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://game.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn disallowed_origin_is_rejected() {
+        let response = warp::test::request()
+            .path("/ships")
+            .header("origin", "https://evil.example.com")
+            .reply(&route())
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+    }
+
+    #[test]
+    fn origins_are_a_scheme_and_a_host() {
+        assert!(is_valid_origin("https://game.example.com"));
+        assert!(is_valid_origin("http://localhost:5173"));
+        assert!(!is_valid_origin("game.example.com"));
+        assert!(!is_valid_origin("https://game.example.com/"));
+        assert!(!is_valid_origin("https://game.example.com/play"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::messages::{DeltaPayload, ServerMessage, StatePayload};
+use crate::ship::TheShip;
+use crate::solar_system::Snapshot;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Smaller changes of a ship are not worth a frame
+const DELTA_EPSILON: f64 = 1e-3;
+
+/// Turns the snapshots into the frames of one connection: a full state every
+/// `keyframe_interval` frames, and only what changed in between
+pub struct DeltaEncoder {
+    keyframe_interval: u32,
+    frames_until_keyframe: u32,
+    known_ships: HashMap<Uuid, TheShip>, // as the client last received them
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: u32) -> Self {
+        Self {
+            keyframe_interval,
+            frames_until_keyframe: 0, // the first frame is always a full state
+            known_ships: HashMap::new(),
+        }
+    }
+
+    pub fn encode(&mut self, snapshot: &Snapshot, ship: serde_json::Value) -> ServerMessage {
+        if self.frames_until_keyframe == 0 {
+            self.frames_until_keyframe = self.keyframe_interval.saturating_sub(1);
+            self.known_ships = snapshot
+                .ships
+                .iter()
+                .map(|ship| (ship.uuid, ship.clone()))
+                .collect();
+
+            return ServerMessage::State {
+                payload: StatePayload::new(snapshot, ship),
+            };
+        }
+        self.frames_until_keyframe -= 1;
+
+        let changed: Vec<TheShip> = snapshot
+            .ships
+            .iter()
+            .filter(|ship| match self.known_ships.get(&ship.uuid) {
+                Some(known) => ship.differs_from(known, DELTA_EPSILON),
+                None => true,
+            })
+            .cloned()
+            .collect();
+
+        let removed: Vec<Uuid> = self
+            .known_ships
+            .keys()
+            .filter(|uuid| !snapshot.ships.iter().any(|ship| ship.uuid == **uuid))
+            .copied()
+            .collect();
+
+        for ship in &changed {
+            self.known_ships.insert(ship.uuid, ship.clone());
+        }
+        for uuid in &removed {
+            self.known_ships.remove(uuid);
+        }
+
+        ServerMessage::Delta {
+            payload: DeltaPayload::new(snapshot, ship, changed, removed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solar_system::{SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// What a client holds after applying the frames it received
+    fn apply(cache: &mut HashMap<Uuid, TheShip>, message: ServerMessage) {
+        match message {
+            ServerMessage::State { payload } => {
+                *cache = payload
+                    .ships
+                    .into_iter()
+                    .map(|ship| (ship.uuid, ship))
+                    .collect();
+            }
+            ServerMessage::Delta { payload } => {
+                for ship in payload.ships {
+                    cache.insert(ship.uuid, ship);
+                }
+                for uuid in payload.removed {
+                    cache.remove(&uuid);
+                }
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deltas_rebuild_the_authoritative_state() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut uuids = Vec::new();
+        for index in 0..3 {
+            let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+            ship.engines.front = index == 0; // one ship moves, the others drift
+            uuids.push(ship.uuid);
+            solar_system.add_ship(Arc::new(Mutex::new(ship)));
+        }
+
+        let mut encoder = DeltaEncoder::new(5);
+        let mut cache = HashMap::new();
+        let mut deltas = 0;
+        for tick in 1..=40 {
+            if tick == 12 {
+                solar_system.remove_ship(uuids[1]);
+            }
+            if tick == 23 {
+                solar_system.add_ship(Arc::new(Mutex::new(TheShip::new_with_uuid(Uuid::new_v4()))));
+            }
+            solar_system.advance(1.0 / 30.0);
+            let snapshot = solar_system.snapshot(0);
+
+            let message = encoder.encode(&snapshot, serde_json::Value::Null);
+            if matches!(message, ServerMessage::Delta { .. }) {
+                deltas += 1;
+            }
+            apply(&mut cache, message);
+
+            assert_eq!(cache.len(), snapshot.ships.len(), "tick {}", tick);
+            for ship in &snapshot.ships {
+                let known = &cache[&ship.uuid];
+                assert!(!ship.differs_from(known, DELTA_EPSILON), "tick {}", tick);
+            }
+        }
+        assert_eq!(deltas, 32); // every fifth frame is a keyframe
+    }
+}
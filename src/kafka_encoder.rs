@@ -0,0 +1,305 @@
+use crate::vec3::{self, VectorFormat};
+use apache_avro::Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanetPosition {
+    pub type_object: String,
+    pub room: String,
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub timestamp: u64, // milliseconds since the epoch
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShipPosition {
+    pub type_object: String,
+    pub room: String,
+    pub uuid: String,
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    #[serde(
+        serialize_with = "vec3::serialize",
+        deserialize_with = "vec3::deserialize"
+    )]
+    pub speed: (f64, f64, f64),
+    pub timestamp: u64,
+}
+
+/// Every planet, moon and ship at the end of a simulation tick, as one record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub room: String,
+    pub tick: u64, // counted since the room was created
+    pub timestamp: u64,
+    pub planets: Vec<PlanetPosition>,
+    pub ships: Vec<ShipPosition>,
+}
+
+const PLANET_POSITION_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "PlanetPosition",
+    "namespace": "outer_wilds",
+    "fields": [
+        {"name": "type_object", "type": "string"},
+        {"name": "room", "type": "string"},
+        {"name": "name", "type": "string"},
+        {"name": "x", "type": "double"},
+        {"name": "y", "type": "double"},
+        {"name": "z", "type": "double"},
+        {"name": "timestamp", "type": "long"}
+    ]
+}"#;
+
+/// `SPEED_TYPE` stands for `speed_type()`, see `ship_position_schema`
+const SHIP_POSITION_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "ShipPosition",
+    "namespace": "outer_wilds",
+    "fields": [
+        {"name": "type_object", "type": "string"},
+        {"name": "room", "type": "string"},
+        {"name": "uuid", "type": "string"},
+        {"name": "name", "type": "string"},
+        {"name": "x", "type": "double"},
+        {"name": "y", "type": "double"},
+        {"name": "z", "type": "double"},
+        {"name": "speed", "type": SPEED_TYPE},
+        {"name": "timestamp", "type": "long"}
+    ]
+}"#;
+
+/// Avro type of the `speed` of `SHIP_POSITION_SCHEMA`, following the `VectorFormat`
+fn speed_type() -> &'static str {
+    match vec3::format() {
+        VectorFormat::Array => r#"{"type": "array", "items": "double"}"#,
+        VectorFormat::Object => {
+            r#"{"type": "record", "name": "Vec3", "fields": [
+                {"name": "x", "type": "double"},
+                {"name": "y", "type": "double"},
+                {"name": "z", "type": "double"}
+            ]}"#
+        }
+    }
+}
+
+fn ship_position_schema() -> String {
+    SHIP_POSITION_SCHEMA.replace("SPEED_TYPE", speed_type())
+}
+
+/// Built from the two record schemas above, which it embeds
+fn world_snapshot_schema() -> String {
+    format!(
+        r#"{{
+    "type": "record",
+    "name": "WorldSnapshot",
+    "namespace": "outer_wilds",
+    "fields": [
+        {{"name": "room", "type": "string"}},
+        {{"name": "tick", "type": "long"}},
+        {{"name": "timestamp", "type": "long"}},
+        {{"name": "planets", "type": {{"type": "array", "items": {}}}}},
+        {{"name": "ships", "type": {{"type": "array", "items": {}}}}}
+    ]
+}}"#,
+        PLANET_POSITION_SCHEMA,
+        ship_position_schema()
+    )
+}
+
+/// Serialization format of the Kafka payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaFormat {
+    Json,
+    Avro,
+}
+
+impl KafkaFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "json" => Some(KafkaFormat::Json),
+            "avro" => Some(KafkaFormat::Avro),
+            _ => None,
+        }
+    }
+
+    pub fn encoder(&self) -> Arc<dyn PositionEncoder> {
+        match self {
+            KafkaFormat::Json => Arc::new(JsonEncoder),
+            KafkaFormat::Avro => Arc::new(AvroEncoder::new()),
+        }
+    }
+}
+
+/// Turns the positions into Kafka payloads, one record or a batch of them
+pub trait PositionEncoder: Send + Sync {
+    fn encode_planet(&self, position: &PlanetPosition) -> Vec<u8>;
+    fn encode_planets(&self, positions: &[PlanetPosition]) -> Vec<u8>;
+    fn encode_ship(&self, position: &ShipPosition) -> Vec<u8>;
+    fn encode_ships(&self, positions: &[ShipPosition]) -> Vec<u8>;
+    fn encode_world(&self, snapshot: &WorldSnapshot) -> Vec<u8>;
+}
+
+pub struct JsonEncoder;
+
+impl JsonEncoder {
+    /// The records are plain numbers and strings, serializing them cannot fail
+    fn encode<T: Serialize + ?Sized>(record: &T) -> Vec<u8> {
+        serde_json::to_vec(record).expect("Kafka records are always serializable")
+    }
+}
+
+impl PositionEncoder for JsonEncoder {
+    fn encode_planet(&self, position: &PlanetPosition) -> Vec<u8> {
+        Self::encode(position)
+    }
+
+    fn encode_planets(&self, positions: &[PlanetPosition]) -> Vec<u8> {
+        Self::encode(positions)
+    }
+
+    fn encode_ship(&self, position: &ShipPosition) -> Vec<u8> {
+        Self::encode(position)
+    }
+
+    fn encode_ships(&self, positions: &[ShipPosition]) -> Vec<u8> {
+        Self::encode(positions)
+    }
+
+    fn encode_world(&self, snapshot: &WorldSnapshot) -> Vec<u8> {
+        Self::encode(snapshot)
+    }
+}
+
+/// Plain Avro datums (no container header, no schema registry id), a batch is an Avro array
+pub struct AvroEncoder {
+    planet_schema: Schema,
+    planets_schema: Schema,
+    ship_schema: Schema,
+    ships_schema: Schema,
+    world_schema: Schema,
+}
+
+impl AvroEncoder {
+    fn new() -> Self {
+        let parse = |schema: &str| Schema::parse_str(schema).expect("Invalid Avro schema");
+        let array_of = |schema: &str| format!(r#"{{"type": "array", "items": {}}}"#, schema);
+
+        Self {
+            planet_schema: parse(PLANET_POSITION_SCHEMA),
+            planets_schema: parse(&array_of(PLANET_POSITION_SCHEMA)),
+            ship_schema: parse(&ship_position_schema()),
+            ships_schema: parse(&array_of(&ship_position_schema())),
+            world_schema: parse(&world_snapshot_schema()),
+        }
+    }
+
+    /// The schemas are written from the structs, a record always matches its schema
+    fn encode<T: Serialize + ?Sized>(schema: &Schema, record: &T) -> Vec<u8> {
+        let value = apache_avro::to_value(record).expect("Kafka records are always serializable");
+        apache_avro::to_avro_datum(schema, value).expect("Kafka record does not match its schema")
+    }
+}
+
+impl PositionEncoder for AvroEncoder {
+    fn encode_planet(&self, position: &PlanetPosition) -> Vec<u8> {
+        Self::encode(&self.planet_schema, position)
+    }
+
+    fn encode_planets(&self, positions: &[PlanetPosition]) -> Vec<u8> {
+        Self::encode(&self.planets_schema, positions)
+    }
+
+    fn encode_ship(&self, position: &ShipPosition) -> Vec<u8> {
+        Self::encode(&self.ship_schema, position)
+    }
+
+    fn encode_ships(&self, positions: &[ShipPosition]) -> Vec<u8> {
+        Self::encode(&self.ships_schema, positions)
+    }
+
+    fn encode_world(&self, snapshot: &WorldSnapshot) -> Vec<u8> {
+        Self::encode(&self.world_schema, snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn earth() -> PlanetPosition {
+        PlanetPosition {
+            type_object: "planet".to_string(),
+            room: "default".to_string(),
+            name: "Earth".to_string(),
+            x: 1.5,
+            y: -2.0,
+            z: 0.25,
+            timestamp: 1234,
+        }
+    }
+
+    #[test]
+    fn planet_round_trips_through_json() {
+        let payload = JsonEncoder.encode_planet(&earth());
+
+        let decoded: PlanetPosition = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(decoded, earth());
+    }
+
+    #[test]
+    fn planet_round_trips_through_avro() {
+        let encoder = AvroEncoder::new();
+        let payload = encoder.encode_planet(&earth());
+
+        let value =
+            apache_avro::from_avro_datum(&encoder.planet_schema, &mut payload.as_slice(), None)
+                .unwrap();
+        let decoded: PlanetPosition = apache_avro::from_value(&value).unwrap();
+        assert_eq!(decoded, earth());
+    }
+
+    #[test]
+    fn world_snapshot_round_trips_through_each_encoder() {
+        let moon = PlanetPosition {
+            type_object: "moon".to_string(),
+            name: "Moon".to_string(),
+            ..earth()
+        };
+        let ship = ShipPosition {
+            type_object: "ship".to_string(),
+            room: "default".to_string(),
+            uuid: "67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(),
+            name: "Hearthian".to_string(),
+            x: 3.0,
+            y: 4.0,
+            z: -5.5,
+            speed: (0.5, 0.0, -0.5),
+            timestamp: 1234,
+        };
+        let world = WorldSnapshot {
+            room: "default".to_string(),
+            tick: 42,
+            timestamp: 1234,
+            planets: vec![earth(), moon],
+            ships: vec![ship],
+        };
+
+        let decoded: WorldSnapshot =
+            serde_json::from_slice(&JsonEncoder.encode_world(&world)).unwrap();
+        assert_eq!(decoded, world);
+
+        let encoder = AvroEncoder::new();
+        let payload = encoder.encode_world(&world);
+        let value =
+            apache_avro::from_avro_datum(&encoder.world_schema, &mut payload.as_slice(), None)
+                .unwrap();
+        let decoded: WorldSnapshot = apache_avro::from_value(&value).unwrap();
+        assert_eq!(decoded, world);
+    }
+}
@@ -1,69 +1,517 @@
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use crate::kafka_encoder::{
+    KafkaFormat, PlanetPosition, PositionEncoder, ShipPosition, WorldSnapshot,
+};
+use crate::metrics::Metrics;
+use crate::ship::TheShip;
+use crate::solar_system::{BodyPosition, Snapshot};
+use futures_util::stream::{self, StreamExt};
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use rdkafka::ClientConfig;
-use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Serialize)]
-struct PlanetPosition {
-    type_object: String,
-    name: String,
-    x: f64,
-    y: f64,
-    z: f64,
-    timestamp: u128,
+/// Records of a tick sent to Kafka at the same time, at most
+const MAX_IN_FLIGHT_RECORDS: usize = 16;
+
+/// Records of the positions of a tick in a room, all stamped with the same timestamp
+fn planet_positions(
+    room: &str,
+    positions: Vec<BodyPosition>,
+    timestamp: u64,
+) -> Vec<PlanetPosition> {
+    positions
+        .into_iter()
+        .map(|body| {
+            let (x, y, z) = body.position;
+            PlanetPosition {
+                type_object: body.kind.as_str().to_string(),
+                room: room.to_string(),
+                name: body.name,
+                x,
+                y,
+                z,
+                timestamp,
+            }
+        })
+        .collect()
+}
+
+/// Records of the ships of a tick in a room, all stamped with the same timestamp
+fn ship_positions(room: &str, ships: &[TheShip], timestamp: u64) -> Vec<ShipPosition> {
+    ships
+        .iter()
+        .map(|ship| {
+            let (x, y, z) = ship.position;
+            ShipPosition {
+                type_object: "ship".to_string(),
+                room: room.to_string(),
+                uuid: ship.uuid.to_string(),
+                name: ship.name.clone(),
+                x,
+                y,
+                z,
+                speed: ship.speed,
+                timestamp,
+            }
+        })
+        .collect()
+}
+
+/// Ship records are keyed by the uuid, which stays the same across renames
+fn ship_key(position: &ShipPosition) -> &str {
+    &position.uuid
+}
+
+/// How the positions of a tick are split into records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaMode {
+    /// One record holding the array of every position, keyed by the timestamp
+    Batch,
+    /// One record per planet, moon or ship, keyed by its name (the uuid for a ship)
+    PerBody,
+}
+
+impl KafkaMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "batch" => Some(KafkaMode::Batch),
+            "per_body" => Some(KafkaMode::PerBody),
+            _ => None,
+        }
+    }
+}
+
+/// What the records are keyed by, so which of them share a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaKeyStrategy {
+    /// The name of the body (the uuid for a ship), the timestamp for a batch
+    Name,
+    Timestamp,
+    /// No key, the records are spread over the partitions
+    None,
+}
+
+impl KafkaKeyStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(KafkaKeyStrategy::Name),
+            "timestamp" => Some(KafkaKeyStrategy::Timestamp),
+            "none" => Some(KafkaKeyStrategy::None),
+            _ => None,
+        }
+    }
+
+    /// Key of a record about the body `name`, or about every body when `name` is `None`
+    fn key(&self, name: Option<&str>, timestamp: u64) -> Option<String> {
+        match (self, name) {
+            (KafkaKeyStrategy::Name, Some(name)) => Some(name.to_string()),
+            (KafkaKeyStrategy::Name, None) | (KafkaKeyStrategy::Timestamp, _) => {
+                Some(timestamp.to_string())
+            }
+            (KafkaKeyStrategy::None, _) => None,
+        }
+    }
+}
+
+/// Settings of the producer, read from the environment in `main`
+pub struct KafkaConfig {
+    pub enabled: bool, // the server runs without Kafka when false
+    pub brokers: String,
+    pub topic: String,
+    pub ship_topic: String,
+    pub world_topic: String,
+    pub mode: KafkaMode,
+    pub format: KafkaFormat,
+    pub key_strategy: KafkaKeyStrategy,
+    /// A failed send is retried this many times, waiting twice longer each time
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
 }
 
 #[derive(Clone)]
 pub struct KafkaProducer {
     producer: FutureProducer,
     topic: String,
+    ship_topic: String,
+    world_topic: String,
+    mode: KafkaMode,
+    encoder: Arc<dyn PositionEncoder>,
+    key_strategy: KafkaKeyStrategy,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    metrics: Arc<Metrics>,
 }
 
 impl KafkaProducer {
-    pub fn new(brokers: &str, topic: &str) -> Result<Self, rdkafka::error::KafkaError> {
+    pub fn new(config: &KafkaConfig, metrics: Arc<Metrics>) -> Result<Self, KafkaError> {
         let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
+            .set("bootstrap.servers", &config.brokers)
             .set("message.timeout.ms", "5000")
             .create()?;
 
         Ok(Self {
             producer,
-            topic: topic.to_string(),
+            topic: config.topic.clone(),
+            ship_topic: config.ship_topic.clone(),
+            world_topic: config.world_topic.clone(),
+            mode: config.mode,
+            encoder: config.format.encoder(),
+            key_strategy: config.key_strategy,
+            max_retries: config.max_retries,
+            retry_base_delay: config.retry_base_delay,
+            metrics,
         })
     }
 
-    pub async fn send_planet_positions(
-        &self,
-        positions: Vec<(String, (f64, f64))>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
+    /// The producer, unless Kafka is disabled or the producer cannot be created.
+    /// The game does not need Kafka, the server then runs without it
+    pub fn connect(config: &KafkaConfig, metrics: Arc<Metrics>) -> Option<Self> {
+        if !config.enabled {
+            println!("Kafka disabled, no positions are sent");
+            return None;
+        }
+        match Self::new(config, metrics) {
+            Ok(producer) => Some(producer),
+            Err(e) => {
+                eprintln!(
+                    "Failed to create Kafka producer, running without Kafka: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
 
-        for (name, (x, y)) in positions {
-            let position = PlanetPosition {
-                type_object: "planet".parse().unwrap(),
-                name,
-                x,
-                y,
-                z: 0.0,
-                timestamp,
-            };
-
-            let payload = serde_json::to_string(&position)?;
-
-            self.producer
-                .send(
-                    FutureRecord::to(&self.topic)
-                        .payload(&payload)
-                        .key(&position.name),
-                    Duration::from_secs(0),
-                )
-                .await
-                .map_err(|(err, _)| err)?;
+    /// Wait for the messages still queued to be delivered, before exiting
+    pub fn flush(&self, timeout: Duration) -> Result<(), KafkaError> {
+        self.producer.flush(timeout)
+    }
+
+    pub async fn send_planet_positions(&self, room: &str, positions: Vec<BodyPosition>) {
+        let timestamp = now_millis();
+
+        let positions = planet_positions(room, positions, timestamp);
+
+        if positions.is_empty() {
+            return;
+        }
+
+        let messages = match self.mode {
+            KafkaMode::Batch => vec![(self.encoder.encode_planets(&positions), None)],
+            KafkaMode::PerBody => positions
+                .iter()
+                .map(|position| {
+                    (
+                        self.encoder.encode_planet(position),
+                        Some(position.name.as_str()),
+                    )
+                })
+                .collect(),
+        };
+        self.send_all(&self.topic, messages, timestamp).await
+    }
+
+    pub async fn send_ship_positions(&self, room: &str, ships: &[TheShip]) {
+        let timestamp = now_millis();
+
+        let positions = ship_positions(room, ships, timestamp);
+
+        if positions.is_empty() {
+            return;
         }
 
-        Ok(())
+        let messages = match self.mode {
+            KafkaMode::Batch => vec![(self.encoder.encode_ships(&positions), None)],
+            KafkaMode::PerBody => positions
+                .iter()
+                .map(|position| (self.encoder.encode_ship(position), Some(ship_key(position))))
+                .collect(),
+        };
+        self.send_all(&self.ship_topic, messages, timestamp).await
+    }
+
+    /// Send the whole world of a tick as one record keyed by the tick number, whatever
+    /// the mode and the key strategy, so a consumer can replay the ticks in order
+    pub async fn send_world_snapshot(&self, room: &str, snapshot: &Snapshot) {
+        let world = world_snapshot(room, snapshot);
+
+        let tick = snapshot.tick.to_string();
+        let payload = self.encoder.encode_world(&world);
+        with_retries(
+            self.max_retries,
+            self.retry_base_delay,
+            &self.metrics,
+            &format!("the world snapshot of tick {} in room {}", tick, room),
+            || self.send(&self.world_topic, &payload, Some(&tick)),
+        )
+        .await
     }
-}
\ No newline at end of file
+
+    /// Send the encoded records of a tick with the name of their body (`None` for a
+    /// batch), retrying the failed ones
+    async fn send_all(&self, topic: &str, messages: Vec<(Vec<u8>, Option<&str>)>, timestamp: u64) {
+        dispatch(messages, |payload, name| async move {
+            let key = self.key_strategy.key(name, timestamp);
+            let label = name.map_or_else(|| timestamp.to_string(), str::to_string);
+            with_retries(
+                self.max_retries,
+                self.retry_base_delay,
+                &self.metrics,
+                &label,
+                || self.send(topic, &payload, key.as_deref()),
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn send(&self, topic: &str, payload: &[u8], key: Option<&str>) -> Result<(), KafkaError> {
+        let record = FutureRecord::<str, [u8]>::to(topic).payload(payload);
+        let record = match key {
+            Some(key) => record.key(key),
+            None => record,
+        };
+
+        self.producer
+            .send(record, Duration::from_secs(0))
+            .await
+            .map(|_| ())
+            .map_err(|(err, _)| err)
+    }
+}
+
+/// Run the sends all in flight together, up to `MAX_IN_FLIGHT_RECORDS`
+async fn dispatch<P, K, F, Fut>(messages: Vec<(P, K)>, send: F)
+where
+    F: Fn(P, K) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    stream::iter(messages)
+        .for_each_concurrent(MAX_IN_FLIGHT_RECORDS, |(payload, key)| send(payload, key))
+        .await
+}
+
+/// Retry a failed send with an exponential backoff, the record is dropped once
+/// `max_retries` retries failed too and logged with its `label`
+async fn with_retries<F, Fut>(
+    max_retries: u32,
+    base_delay: Duration,
+    metrics: &Metrics,
+    label: &str,
+    send: F,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<(), KafkaError>>,
+{
+    let mut delay = base_delay;
+
+    for retry in 0..=max_retries {
+        let error = match send().await {
+            Ok(()) => return,
+            Err(error) => error,
+        };
+
+        if retry == max_retries {
+            eprintln!(
+                "Failed to send {} to Kafka after {} retries: {}",
+                label, max_retries, error
+            );
+            metrics.kafka_failure();
+            return;
+        }
+
+        metrics.kafka_retry();
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// The world of a tick in the room, as sent to Kafka and written by the recorder
+pub fn world_snapshot(room: &str, snapshot: &Snapshot) -> WorldSnapshot {
+    let timestamp = now_millis();
+    WorldSnapshot {
+        room: room.to_string(),
+        tick: snapshot.tick,
+        timestamp,
+        planets: planet_positions(room, snapshot.positions.clone(), timestamp),
+        ships: ship_positions(room, &snapshot.ships, timestamp),
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::kafka_encoder::JsonEncoder;
+    use crate::solar_system::BodyKind;
+    use uuid::Uuid;
+
+    #[test]
+    fn batched_payload_deserializes_into_the_positions() {
+        let bodies = vec![
+            BodyPosition {
+                name: "Earth".to_string(),
+                kind: BodyKind::Planet,
+                position: (1.5, -2.0, 0.25),
+            },
+            BodyPosition {
+                name: "Moon".to_string(),
+                kind: BodyKind::Moon,
+                position: (3.0, 4.0, -5.5),
+            },
+        ];
+
+        let payload = JsonEncoder.encode_planets(&planet_positions("default", bodies, 1234));
+        let decoded: Vec<PlanetPosition> = serde_json::from_slice(&payload).unwrap();
+
+        assert_eq!(
+            decoded,
+            vec![
+                PlanetPosition {
+                    type_object: "planet".to_string(),
+                    room: "default".to_string(),
+                    name: "Earth".to_string(),
+                    x: 1.5,
+                    y: -2.0,
+                    z: 0.25,
+                    timestamp: 1234,
+                },
+                PlanetPosition {
+                    type_object: "moon".to_string(),
+                    room: "default".to_string(),
+                    name: "Moon".to_string(),
+                    x: 3.0,
+                    y: 4.0,
+                    z: -5.5,
+                    timestamp: 1234,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ship_position_is_keyed_by_the_uuid() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        ship.name = "Hearthian".to_string();
+        ship.position = (1.0, 2.0, 3.0);
+        ship.speed = (0.5, 0.0, -0.5);
+
+        let positions = ship_positions("alpha", std::slice::from_ref(&ship), 1234);
+        let json: serde_json::Value = serde_json::to_value(&positions[0]).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type_object": "ship",
+                "room": "alpha",
+                "uuid": ship.uuid.to_string(),
+                "name": "Hearthian",
+                "x": 1.0,
+                "y": 2.0,
+                "z": 3.0,
+                "speed": [0.5, 0.0, -0.5],
+                "timestamp": 1234,
+            })
+        );
+        assert_eq!(ship_key(&positions[0]), ship.uuid.to_string());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_record_is_dispatched_within_one_interval() {
+        let dispatched = std::sync::Mutex::new(Vec::new());
+        let messages: Vec<(String, String)> = (0..40)
+            .map(|i| (format!("payload {}", i), format!("key {}", i)))
+            .collect();
+        let start = tokio::time::Instant::now();
+
+        dispatch(messages, |_payload, key| {
+            let dispatched = &dispatched;
+            async move {
+                // Stand-in for the broker acknowledging the record
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                dispatched.lock().unwrap().push(key);
+            }
+        })
+        .await;
+
+        assert_eq!(dispatched.lock().unwrap().len(), 40);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_sends_are_retried_with_a_backoff() {
+        let metrics = Metrics::new();
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let start = tokio::time::Instant::now();
+
+        with_retries(3, Duration::from_millis(100), &metrics, "Earth", || async {
+            // Stand-in for a producer failing twice, then delivering the record
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                0 | 1 => Err(KafkaError::MessageProduction(
+                    rdkafka::types::RDKafkaErrorCode::QueueFull,
+                )),
+                _ => Ok(()),
+            }
+        })
+        .await;
+
+        let report = metrics.report();
+        assert_eq!(attempts.into_inner(), 3);
+        assert_eq!(report.kafka_retries, 2);
+        assert_eq!(report.kafka_failures, 0);
+        // Waited 100ms, then 200ms
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn key_strategies_produce_their_key_bytes() {
+        let key = |strategy: KafkaKeyStrategy, name| strategy.key(name, 1234);
+
+        assert_eq!(
+            key(KafkaKeyStrategy::Name, Some("Earth"))
+                .as_deref()
+                .map(str::as_bytes),
+            Some(&b"Earth"[..])
+        );
+        assert_eq!(
+            key(KafkaKeyStrategy::Name, None)
+                .as_deref()
+                .map(str::as_bytes),
+            Some(&b"1234"[..])
+        );
+        assert_eq!(
+            key(KafkaKeyStrategy::Timestamp, Some("Earth"))
+                .as_deref()
+                .map(str::as_bytes),
+            Some(&b"1234"[..])
+        );
+        assert_eq!(key(KafkaKeyStrategy::None, Some("Earth")), None);
+        assert_eq!(key(KafkaKeyStrategy::None, None), None);
+    }
+
+    #[test]
+    fn server_starts_without_kafka_when_disabled() {
+        // Not even essential in strict mode then
+        let config = Config::from_lookup(|name| match name {
+            "STRICT_CONFIG" => Some("true".to_string()),
+            "WEBSOCKET_URL" => Some("ws://127.0.0.1:3012/socket".to_string()),
+            "BACKEND_URL" => Some("https://api.example.com".to_string()),
+            "ALLOWED_ORIGINS" => Some("https://game.example.com".to_string()),
+            "KAFKA_ENABLED" => Some("false".to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|errors| panic!("{:?}", errors));
+
+        assert!(!config.kafka.enabled);
+        assert!(KafkaProducer::connect(&config.kafka, Arc::new(Metrics::new())).is_none());
+    }
+}
@@ -1,300 +1,1096 @@
+mod admin;
+mod api_urls;
+mod compression;
+mod config;
+mod connections;
+mod cors;
+mod delta;
+mod kafka_encoder;
 mod kafka_producer;
+mod messages;
+mod metrics;
+mod planets_config;
+mod probe;
+mod quaternion;
+mod rate_limiter;
+mod recorder;
+mod replay;
+mod rooms;
+mod sessions;
 mod ship;
+mod socket;
+mod solar_system;
+mod timestep;
+mod vec3;
 
+use crate::config::Config;
+use crate::connections::{Connections, Directive};
 use crate::kafka_producer::KafkaProducer;
+use crate::messages::{ServerMessage, StatePayload};
+use crate::planets_config::PlanetConfig;
 use dotenv::dotenv;
-use serde::Serialize;
-use serde_json::{self, json};
-use ship::TheShip;
-use std::collections::HashMap;
-use std::f64::consts::PI;
-use std::sync::{Arc, Mutex};
+use metrics::Metrics;
+use rooms::{Room, Rooms, SNAPSHOT_CHANNEL_CAPACITY};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sessions::SessionCache;
+use socket::SocketContext;
+use solar_system::{Snapshot, SolarSystem};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::{env, thread};
+use timestep::FixedTimestep;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, watch};
 use uuid::Uuid;
+use vec3::Vec3;
+use warp::http::StatusCode;
 use warp::Filter;
-use ws::{Handler, Handshake, Message, Result, Sender};
 
-#[derive(Clone)]
-struct Planet {
+/// How long the clients and Kafka get to finish when the server stops
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// Steps run at most by one tick to catch up with the wall clock
+const MAX_CATCH_UP_STEPS: u32 = 5;
+const DEFAULT_PREDICTION_STEPS: u32 = 120;
+const MAX_PREDICTION_STEPS: u32 = 2000;
+/// Seconds between two predicted points, at most `MAX_PREDICTION_STEP`
+const DEFAULT_PREDICTION_STEP: f64 = 0.1;
+const MAX_PREDICTION_STEP: f64 = 1.0;
+
+/// Public view of a ship for the REST API
+#[derive(Serialize)]
+struct ShipSummary {
+    uuid: Uuid,
     name: String,
-    distance_from_sun: f64,
-    angle: f64,
-    angular_velocity: f64, // radians per second
+    #[serde(serialize_with = "vec3::serialize")]
+    position: (f64, f64, f64),
 }
 
-impl Planet {
-    fn new(name: &str, distance_from_sun: f64, orbital_period: f64) -> Self {
-        Self {
-            name: name.to_string(),
-            distance_from_sun,
-            angle: 0.0,
-            angular_velocity: 2.0 * PI / orbital_period,
-        }
-    }
+/// Public view of a planet or a moon for the REST API
+#[derive(Serialize)]
+struct PlanetSummary {
+    name: String,
+    kind: &'static str,
+    #[serde(serialize_with = "vec3::serialize")]
+    position: (f64, f64, f64),
+}
 
-    fn update_position(&mut self, delta_time: f64) {
-        self.angle += self.angular_velocity * delta_time;
-        if self.angle > 2.0 * PI {
-            self.angle -= 2.0 * PI;
+/// Query of `GET /world`, `POST /pause` and `POST /step`
+#[derive(Deserialize)]
+struct RoomQuery {
+    room: Option<String>,
+}
+
+impl RoomQuery {
+    /// The room given by `room`, the default one when not given
+    fn room(&self, rooms: &Rooms) -> Option<Arc<Room>> {
+        match &self.room {
+            Some(name) => rooms.get(name),
+            None => Some(rooms.default_room()),
         }
     }
+}
 
-    fn position(&self) -> (f64, f64) {
-        (
-            self.distance_from_sun * self.angle.cos(),
-            self.distance_from_sun * self.angle.sin(),
-        )
-    }
+/// Body of `POST /predict`
+#[derive(Deserialize)]
+struct PredictionRequest {
+    uuid: Uuid,
+    steps: Option<u32>,
+    step: Option<f64>, // seconds
 }
 
-#[derive(Clone)]
-struct SolarSystem {
-    planets: Vec<Planet>,
-    ships: HashMap<Uuid, Arc<Mutex<TheShip>>>,
+/// GET /ships, the ships currently in the default room
+fn ships_route(
+    rooms: Arc<Rooms>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("ships")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            // Copy what is needed and release the lock before serializing
+            let ships: Vec<ShipSummary> = rooms
+                .default_room()
+                .solar_system
+                .read()
+                .ships
+                .values()
+                .map(|ship| {
+                    let ship = ship.lock();
+                    ShipSummary {
+                        uuid: ship.uuid,
+                        name: ship.name.clone(),
+                        position: ship.position,
+                    }
+                })
+                .collect();
+            warp::reply::json(&ships)
+        })
 }
 
-impl SolarSystem {
-    fn new() -> Self {
-        Self {
-            planets: vec![
-                Planet::new("Mercury", 50.0, 0.24 * 60.0),
-                Planet::new("Venus", 70.0, 0.62 * 60.0),
-                Planet::new("Earth", 90.0, 1.0 * 60.0),
-                Planet::new("Mars", 110.0, 1.88 * 60.0),
-                Planet::new("Jupiter", 150.0, 11.86 * 60.0),
-            ],
-            ships: HashMap::new(),
-        }
-    }
+/// GET /ships/{uuid}, the full state of one ship, whatever its room
+fn ship_route(
+    rooms: Arc<Rooms>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("ships")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move |uuid: Uuid| {
+            let ship = rooms.find_ship(uuid).map(|ship| ship.lock().to_json());
 
-    fn update(&mut self, delta_time: f64) {
-        for planet in &mut self.planets {
-            planet.update_position(delta_time);
-        }
+            match ship {
+                Some(ship) => warp::reply::with_status(warp::reply::json(&ship), StatusCode::OK),
+                None => warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_ship" })),
+                    StatusCode::NOT_FOUND,
+                ),
+            }
+        })
+}
 
-        for ship in self.ships.values_mut() {
-            ship.lock().unwrap().update(delta_time);
-        }
-    }
+/// GET /planets, where the planets and the moons of the default room are now
+fn planets_route(
+    rooms: Arc<Rooms>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("planets")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let positions = rooms.default_room().solar_system.read().positions();
+            let planets: Vec<PlanetSummary> = positions
+                .into_iter()
+                .map(|body| PlanetSummary {
+                    name: body.name,
+                    kind: body.kind.as_str(),
+                    position: body.position,
+                })
+                .collect();
+            warp::reply::json(&planets)
+        })
+}
 
-    fn add_ship(&mut self, ship: Arc<Mutex<TheShip>>) {
-        let uuid = ship.lock().unwrap().uuid;
-        self.ships.insert(uuid, ship);
-    }
+/// POST /planets, adds a planet to every room, for the holders of the `ADMIN_TOKEN`
+fn add_planet_route(
+    rooms: Arc<Rooms>,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("planets")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::admin_only(admin_token))
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .map(move |planet: PlanetConfig| {
+            let name = planet.name.clone();
+            let added = planets_config::build_planets(&[planet])
+                .map_err(|error| {
+                    let body = json!({ "error": "invalid_planet", "reason": error.to_string() });
+                    (StatusCode::BAD_REQUEST, body)
+                })
+                .and_then(|mut planets| {
+                    rooms
+                        .add_planet(planets.remove(0))
+                        .map_err(|reason| (StatusCode::CONFLICT, json!({ "error": reason })))
+                });
 
-    fn remove_ship(&mut self, uuid: Uuid) {
-        self.ships.remove(&uuid);
-    }
+            match added {
+                Ok(()) => warp::reply::with_status(
+                    warp::reply::json(&json!({ "name": name })),
+                    StatusCode::CREATED,
+                ),
+                Err((status, body)) => warp::reply::with_status(warp::reply::json(&body), status),
+            }
+        })
+}
 
-    fn positions(&self) -> Vec<(String, (f64, f64))> {
-        self.planets
-            .iter()
-            .map(|p| (p.name.clone(), p.position()))
-            .collect()
-    }
+/// DELETE /planets/{name}, removes a planet from every room, for the holders of the `ADMIN_TOKEN`
+fn remove_planet_route(
+    rooms: Arc<Rooms>,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("planets")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(admin::admin_only(admin_token))
+        .map(move |name: String| {
+            // "Giant%27s%20Deep" in the path
+            let name = percent_encoding::percent_decode_str(&name).decode_utf8_lossy();
+            if rooms.remove_planet(&name) {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "name": name })),
+                    StatusCode::OK,
+                )
+            } else {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_planet" })),
+                    StatusCode::NOT_FOUND,
+                )
+            }
+        })
+}
+
+/// POST /kick/{uuid}, removes a ship and closes its socket, for the holders of the `ADMIN_TOKEN`
+fn kick_route(
+    rooms: Arc<Rooms>,
+    connections: Arc<Connections>,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("kick")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::admin_only(admin_token))
+        .map(move |uuid: Uuid| {
+            let ship = rooms
+                .room_of(uuid)
+                .and_then(|room| room.solar_system.write().remove_ship(uuid));
+            if ship.is_none() {
+                return warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_ship" })),
+                    StatusCode::NOT_FOUND,
+                );
+            }
+
+            println!("Ship {} kicked", uuid);
+            // Without its ship, the client has nothing left to do
+            connections.send_message(uuid, ServerMessage::Kicked);
+            connections.send(uuid, Directive::Close);
+            warp::reply::with_status(warp::reply::json(&json!({ "uuid": uuid })), StatusCode::OK)
+        })
 }
 
-struct Server {
-    out: Sender,
-    solar_system: Arc<Mutex<SolarSystem>>,
-    last_update: Instant,
-    ship_uuid: Uuid,
+/// GET /world, the latest state frame of a room, the default one unless `?room=` is given
+fn world_route(
+    rooms: Arc<Rooms>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("world")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<RoomQuery>())
+        .map(move |query: RoomQuery| {
+            match query.room(&rooms) {
+                // The frame of the WebSocket, without a ship of its own
+                Some(room) => warp::reply::with_status(
+                    warp::reply::json(&StatePayload::new(&room.latest(), serde_json::Value::Null)),
+                    StatusCode::OK,
+                ),
+                None => warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_room" })),
+                    StatusCode::NOT_FOUND,
+                ),
+            }
+        })
 }
 
-impl Handler for Server {
-    fn on_open(&mut self, _: Handshake) -> Result<()> {
-        println!("Websocket opened. Ship uuid {}", self.ship_uuid);
-        self.last_update = Instant::now();
-        let solar_system_clone = Arc::clone(&self.solar_system);
-        let out_clone = self.out.clone();
+/// POST /pause, pause a room or resume it when it is paused
+fn pause_route(
+    rooms: Arc<Rooms>,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("pause")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::admin_only(admin_token))
+        .and(warp::query::<RoomQuery>())
+        .map(move |query: RoomQuery| {
+            let Some(room) = query.room(&rooms) else {
+                return warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_room" })),
+                    StatusCode::NOT_FOUND,
+                );
+            };
 
-        let ship = Arc::new(Mutex::new(TheShip::new()));
-        let ship_clone = ship.clone();
-        self.ship_uuid = ship.lock().unwrap().uuid;
+            let mut solar_system = room.solar_system.write();
+            let paused = !solar_system.is_paused();
+            solar_system.set_paused(paused);
+            println!(
+                "Room {} {}",
+                room.name,
+                if paused { "paused" } else { "resumed" }
+            );
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "room": room.name, "paused": paused })),
+                StatusCode::OK,
+            )
+        })
+}
+
+/// POST /step, run a single step of a paused room
+fn step_route(
+    rooms: Arc<Rooms>,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("step")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::admin_only(admin_token))
+        .and(warp::query::<RoomQuery>())
+        .map(move |query: RoomQuery| {
+            let Some(room) = query.room(&rooms) else {
+                return warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "unknown_room" })),
+                    StatusCode::NOT_FOUND,
+                );
+            };
 
-        {
-            let mut solar_system = solar_system_clone.lock().unwrap();
-            solar_system.add_ship(ship);
+            // Run by the next tick of the simulation loop
+            if room.solar_system.write().request_step() {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "room": room.name })),
+                    StatusCode::ACCEPTED,
+                )
+            } else {
+                warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "not_paused" })),
+                    StatusCode::CONFLICT,
+                )
+            }
+        })
+}
+
+/// POST /predict, where a ship drifts under gravity if its engines stay off
+fn predict_route(
+    rooms: Arc<Rooms>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("predict")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and_then(move |request: PredictionRequest| {
+            let rooms = Arc::clone(&rooms);
+            async move { Ok::<_, warp::Rejection>(predict(&rooms, request).await) }
+        })
+}
+
+/// Answer of `POST /predict`. The world and the ship are copied under the lock, the steps
+/// then run on a blocking thread, the simulation does not wait for them
+async fn predict(
+    rooms: &Rooms,
+    request: PredictionRequest,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    let steps = request.steps.unwrap_or(DEFAULT_PREDICTION_STEPS);
+    let step = request.step.unwrap_or(DEFAULT_PREDICTION_STEP);
+    if steps == 0 || steps > MAX_PREDICTION_STEPS {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "invalid_steps" })),
+            StatusCode::BAD_REQUEST,
+        );
+    }
+    if !(step > 0.0 && step <= MAX_PREDICTION_STEP) {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "invalid_step" })),
+            StatusCode::BAD_REQUEST,
+        );
+    }
+
+    let copies = rooms.room_of(request.uuid).and_then(|room| {
+        let solar_system = room.solar_system.read();
+        let ship = solar_system.ships.get(&request.uuid)?.lock().clone();
+        Some((solar_system.clone(), ship))
+    });
+    let Some((world, ship)) = copies else {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "unknown_ship" })),
+            StatusCode::NOT_FOUND,
+        );
+    };
+
+    match tokio::task::spawn_blocking(move || world.predict(ship, steps, step)).await {
+        Ok(points) => warp::reply::with_status(
+            warp::reply::json(&json!({
+                "step": step,
+                "points": points.into_iter().map(Vec3::from).collect::<Vec<_>>(),
+            })),
+            StatusCode::OK,
+        ),
+        Err(error) => {
+            eprintln!("Prediction failed: {}", error);
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "error": "prediction_failed" })),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
         }
+    }
+}
 
-        thread::spawn(move || {
-            loop {
-                // Envoyer les informations des planètes et du vaisseau via la websocket
-                let positions = {
-                    let solar_system = solar_system_clone.lock().unwrap();
-                    solar_system.positions()
-                };
+/// GET /health, answers as long as the server is up
+fn health_route(
+    metrics: Arc<Metrics>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("health")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            warp::reply::json(&json!({
+                "status": "ok",
+                "uptime_secs": metrics.uptime_secs(),
+            }))
+        })
+}
 
-                let ships: Vec<TheShip> = {
-                    let solar_system = solar_system_clone.lock().unwrap();
-                    solar_system
-                        .ships
-                        .values()
-                        .into_iter()
-                        .map(|ship| ship.lock().unwrap().clone())
-                        .collect()
-                };
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
 
-                let ship_info = { ship_clone.lock().unwrap().to_json() };
+    // Récupérer et afficher la configuration au démarrage
+    let config = Config::from_env().unwrap_or_else(|errors| {
+        for error in &errors {
+            eprintln!("Invalid configuration: {}", error);
+        }
+        std::process::exit(1);
+    });
+    config.log();
+    vec3::set_format(config.vector_format);
 
-                let message = json!({
-                    "planets": positions,
-                    "ship": ship_info,
-                    "ships": ships,
-                });
-                out_clone.send(Message::text(message.to_string())).unwrap();
+    // Checked before anything starts
+    let resolved = |address: Result<SocketAddr, String>| {
+        address.unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })
+    };
+    let http_address = resolved(config.http_address());
+    let websocket_address = resolved(config.websocket_address());
+    let replay = config.replay_path.as_ref().map(|path| {
+        recorder::read_recording(path).unwrap_or_else(|error| {
+            eprintln!("Invalid REPLAY_PATH {}: {}", path, error);
+            std::process::exit(1);
+        })
+    });
 
-                thread::sleep(Duration::from_millis(1000/30))
-            }
+    let mut solar_system = SolarSystem::new(config.gravitational_constant)
+        .with_surface_collision(config.surface_collision)
+        .with_world_boundary(config.world_radius, config.world_boundary)
+        .with_ship_settings(config.ship)
+        .with_gravity_softening(config.gravity_softening)
+        .with_sun_mass(config.sun_mass)
+        .with_planet_motion(config.planet_motion);
+    if let Some(path) = &config.planets_config {
+        let planets = planets_config::load_planets(path).unwrap_or_else(|error| {
+            eprintln!("Invalid PLANETS_CONFIG {}: {}", path, error);
+            std::process::exit(1);
         });
+        solar_system = solar_system.with_planets(planets);
+    }
+    solar_system = solar_system.with_world_scale(config.world_scale);
+    // Same seed, same ship uuids, to reproduce a session
+    if let Some(seed) = config.simulation_seed {
+        solar_system = solar_system.with_seed(seed);
+    }
+    // Every room starts from this solar system, the HTTP API shows the default room
+    let rooms = Arc::new(Rooms::new(solar_system, config.max_players));
+    let connections = Arc::new(Connections::default());
+    let metrics = Arc::new(Metrics::new());
+    // Every loop stops once this turns true
+    let (shutdown_sender, shutdown) = watch::channel(false);
+
+    let auth_api_url = api_urls::route(config.backend_url.clone(), config.websocket_url.clone());
+
+    let metrics_clone = Arc::clone(&metrics);
+    let metrics_route = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || warp::reply::json(&metrics_clone.report()));
 
-        Ok(())
+    let cors = cors::cors(config.allowed_origins.as_deref());
+
+    let admin_token = &config.admin_token;
+    let routes = auth_api_url
+        .or(ships_route(Arc::clone(&rooms)))
+        .or(ship_route(Arc::clone(&rooms)))
+        .or(planets_route(Arc::clone(&rooms)))
+        .or(add_planet_route(Arc::clone(&rooms), admin_token.clone()))
+        .or(remove_planet_route(Arc::clone(&rooms), admin_token.clone()))
+        .or(world_route(Arc::clone(&rooms)))
+        .or(pause_route(Arc::clone(&rooms), admin_token.clone()))
+        .or(step_route(Arc::clone(&rooms), admin_token.clone()))
+        .or(predict_route(Arc::clone(&rooms)))
+        .or(kick_route(
+            Arc::clone(&rooms),
+            Arc::clone(&connections),
+            admin_token.clone(),
+        ))
+        .or(health_route(Arc::clone(&metrics)))
+        .or(metrics_route)
+        .recover(admin::handle_rejection)
+        .with(cors);
+
+    let kafka_producer = KafkaProducer::connect(&config.kafka, Arc::clone(&metrics));
+
+    // Every tick of every room, for the Kafka world snapshots
+    let (world_sender, _) = broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY);
+
+    if let Some(recording) = replay {
+        // Task to play the recording to the clients, nothing is simulated
+        let rooms_clone = Arc::clone(&rooms);
+        let replay_loop = config.replay_loop;
+        let shutdown_clone = shutdown.clone();
+        tokio::spawn(async move {
+            let publish = |name: &str, mut snapshot: Snapshot| {
+                // Only to the rooms some client joined
+                if let Some(room) = rooms_clone.get(name) {
+                    // On the clock of the server, as the answers to the time syncs
+                    snapshot.server_time_ms = rooms_clone.server_time_ms();
+                    room.publish(snapshot);
+                }
+            };
+            replay::play(&recording, replay_loop, shutdown_clone, publish).await;
+            println!("End of the replay");
+        });
+    } else {
+        tokio::spawn(simulation_loop(
+            Arc::clone(&rooms),
+            Arc::clone(&connections),
+            world_sender.clone(),
+            Arc::clone(&metrics),
+            config.tick_hz,
+            shutdown.clone(),
+        ));
     }
 
-    fn on_message(&mut self, msg: Message) -> Result<()> {
-        let msg_text = msg.into_text()?;
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&msg_text) {
-            if let Some(data) = data.get("data") {
-                if let Some(engines) = data.get("engines") {
-                    let solar_system = self.solar_system.lock().unwrap();
-                    let ship = solar_system.ships.get(&self.ship_uuid).unwrap();
-                    let mut ship = ship.lock().unwrap();
-                    ship.engines.front = engines.get("front").unwrap().as_bool().unwrap();
-                    ship.engines.back = engines.get("back").unwrap().as_bool().unwrap();
-                    ship.engines.left = engines.get("left").unwrap().as_bool().unwrap();
-                    ship.engines.right = engines.get("right").unwrap().as_bool().unwrap();
-                    ship.engines.up = engines.get("up").unwrap().as_bool().unwrap();
-                    ship.engines.down = engines.get("down").unwrap().as_bool().unwrap();
+    // Without Kafka, only the game runs
+    if let Some(kafka_producer_clone) = kafka_producer.clone() {
+        let rooms_clone = Arc::clone(&rooms);
+        let shutdown_clone = shutdown.clone();
+        let kafka_ship_positions = config.kafka_ship_positions;
+
+        // Thread to send position to Kafka (not the same frequency as the solar system update)
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+            while !*shutdown_clone.borrow() {
+                interval.tick().await;
+
+                for room in rooms_clone.all() {
+                    let snapshot = room.latest();
+                    let positions = snapshot.positions.clone();
+
+                    // Failed records are retried, logged and counted by the producer
+                    tokio::join!(
+                        kafka_producer_clone.send_planet_positions(&room.name, positions),
+                        async {
+                            if kafka_ship_positions {
+                                kafka_producer_clone
+                                    .send_ship_positions(&room.name, &snapshot.ships)
+                                    .await
+                            }
+                        }
+                    );
                 }
+            }
+        });
+    }
 
-                if let Some(rotation) = data.get("rotation") {
-                    let solar_system = self.solar_system.lock().unwrap();
-                    let ship = solar_system.ships.get(&self.ship_uuid).unwrap();
-                    let mut ship = ship.lock().unwrap();
-                    ship.rotation_engines.left = rotation.get("left").unwrap().as_bool().unwrap();
-                    ship.rotation_engines.right = rotation.get("right").unwrap().as_bool().unwrap();
-                    ship.rotation_engines.up = rotation.get("up").unwrap().as_bool().unwrap();
-                    ship.rotation_engines.down = rotation.get("down").unwrap().as_bool().unwrap();
+    // Task to send every tick of the world to Kafka, to replay it later
+    let world_snapshots_producer = kafka_producer
+        .clone()
+        .filter(|_| config.kafka_world_snapshots);
+    if let Some(kafka_producer_clone) = world_snapshots_producer {
+        let mut snapshots = world_sender.subscribe();
+        let mut shutdown = shutdown.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.changed() => break,
+                    received = snapshots.recv() => match received {
+                        Ok((room, snapshot)) => {
+                            kafka_producer_clone.send_world_snapshot(&room, &snapshot).await
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            eprintln!("Kafka is too slow, {} world snapshots skipped", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
                 }
             }
+        });
+    }
+
+    // Task to write every tick of the world to `RECORD_PATH`, for a replay
+    let recorder = config.record_path.clone().map(|path| {
+        let snapshots = world_sender.subscribe();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            println!("Recording the world to {}", path);
+            if let Err(e) = recorder::record(&path, snapshots, shutdown).await {
+                eprintln!("Failed to record the world to {}: {}", path, e);
+            }
+        })
+    });
+
+    let socket_context = SocketContext {
+        rooms: Arc::clone(&rooms),
+        connections: Arc::clone(&connections),
+        sessions: Arc::new(parking_lot::Mutex::new(SessionCache::new(
+            config.session_ttl,
+        ))),
+        metrics: Arc::clone(&metrics),
+        max_commands_per_second: config.max_commands_per_second,
+        chat_range: config.chat_range,
+        keyframe_interval: config.keyframe_interval,
+        broadcast_stride: config.broadcast_stride(),
+        ping_interval: config.ping_interval,
+        pong_timeout: config.pong_timeout,
+        replaying: config.replay_path.is_some(),
+        shutdown: shutdown.clone(),
+    };
+    let socket = socket::route(socket_context);
+
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        println!("Shutting down");
+        let _ = shutdown_sender.send(true);
+    });
+
+    // Stops accepting connections on shutdown, the open ones close themselves
+    if config.single_port {
+        println!("HTTP and WebSocket server listening on {}", http_address);
+        let routes = warp::path("api").and(routes).or(socket);
+        serve(routes, http_address, shutdown.clone()).await;
+    } else {
+        println!("HTTP server listening on {}", http_address);
+        tokio::spawn(serve(routes, http_address, shutdown.clone()));
+        println!("WebSocket server listening on {}/socket", websocket_address);
+        serve(socket, websocket_address, shutdown.clone()).await;
+    }
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE_PERIOD;
+    while metrics.connected_clients() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // The last snapshots are still in its buffer
+    if let Some(recorder) = recorder {
+        let _ = recorder.await;
+    }
+
+    if let Some(kafka_producer) = &kafka_producer {
+        if let Err(e) = kafka_producer.flush(SHUTDOWN_GRACE_PERIOD) {
+            eprintln!("Failed to flush Kafka: {}", e);
         }
-        Ok(())
     }
+}
+
+/// Update the solar system of every room on every tick until the shutdown flag turns true,
+/// the only writer of the snapshots. Each of them is also sent to `worlds` with its room
+async fn simulation_loop(
+    rooms: Arc<Rooms>,
+    connections: Arc<Connections>,
+    worlds: broadcast::Sender<(String, Arc<Snapshot>)>,
+    metrics: Arc<Metrics>,
+    tick_hz: u32,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / tick_hz as f64));
+    // The clock of tokio, paused and advanced by hand in the tests
+    let mut last_update = tokio::time::Instant::now();
+    // The physics always moves by the same step, whatever the scheduling
+    let mut timestep = FixedTimestep::new(1.0 / tick_hz as f64, MAX_CATCH_UP_STEPS);
+
+    while !*shutdown.borrow() {
+        interval.tick().await;
+
+        let now = tokio::time::Instant::now();
+        let delta_time = (now - last_update).as_secs_f64();
+        last_update = now;
+
+        let steps = timestep.steps(delta_time);
+        if steps == 0 {
+            continue;
+        }
 
-    fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
-        let solar_system_clone = Arc::clone(&self.solar_system);
-        {
-            let mut solar_system = solar_system_clone.lock().unwrap();
-            solar_system.remove_ship(self.ship_uuid);
+        let server_time_ms = rooms.server_time_ms();
+        for room in rooms.all() {
+            for (uuid, reason) in room.apply_commands() {
+                let error = ServerMessage::Error {
+                    reason: reason.to_string(),
+                };
+                connections.send_message(uuid, error);
+            }
+            let snapshot = room.advance(steps, timestep.step(), server_time_ms);
+            // Fails only when the world snapshots are not sent
+            let _ = worlds.send((room.name.clone(), snapshot));
         }
-        println!("WebSocket closing for ({:?}) {}", code, reason);
+        rooms.drop_empty();
+        metrics.record_tick(delta_time);
     }
 }
 
-#[derive(Serialize)]
-struct ApiUrls {
-    backend_url: String,
-    websocket_url: String,
+/// Serve the routes on `address` until the server stops
+async fn serve<F, R>(routes: F, address: SocketAddr, mut shutdown: watch::Receiver<bool>)
+where
+    F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    R: warp::Reply,
+{
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(address, async move {
+        let _ = shutdown.changed().await;
+    });
+    server.await;
 }
 
+/// Resolves on the first SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
 
-#[tokio::main]
-async fn main() {
-    dotenv().ok();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_TICK_HZ;
+    use crate::rooms::DEFAULT_ROOM;
+    use crate::solar_system::DEFAULT_GRAVITATIONAL_CONSTANT;
+
+    #[tokio::test]
+    async fn ships_route_lists_every_ship() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        for _ in 0..3 {
+            rooms.join(DEFAULT_ROOM, &mut None).unwrap();
+        }
+        rooms.join("alpha", &mut None).unwrap();
+        let route = ships_route(Arc::new(rooms));
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/ships")
+            .reply(&route)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let ships: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(ships.len(), 3);
+        assert_eq!(ships[0]["name"], "Hearthian");
+    }
+
+    #[tokio::test]
+    async fn ship_route_finds_a_ship_by_uuid() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        let (_, uuid) = rooms.join("alpha", &mut None).unwrap();
+        let route = ship_route(Arc::new(rooms));
+
+        let response = warp::test::request()
+            .method("GET")
+            .path(&format!("/ships/{}", uuid))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 200);
+        let ship: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(ship["uuid"], uuid.to_string());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path(&format!("/ships/{}", Uuid::new_v4()))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn predict_route_traces_the_drift_without_moving_the_ship() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        let (room, uuid) = rooms.join(DEFAULT_ROOM, &mut None).unwrap();
+        let before = room.solar_system.read().ships[&uuid].lock().position;
+        let route = predict_route(Arc::new(rooms));
 
-    // Récupérer et afficher la variable d'environnement au démarrage
-    let websocket_url = env::var("WEBSOCKET_URL").unwrap_or_else(|_| "ws://127.0.0.1:3012".to_string());
-    println!("WEBSOCKET_URL: {}", websocket_url);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/predict")
+            .json(&json!({ "uuid": uuid, "steps": 10, "step": 0.5 }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 200);
+        let prediction: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(prediction["step"], 0.5);
+        let points = prediction["points"].as_array().unwrap();
+        assert!(!points.is_empty() && points.len() <= 10);
+        let after = room.solar_system.read().ships[&uuid].lock().position;
+        assert_eq!(before, after);
 
-    let solar_system = Arc::new(Mutex::new(SolarSystem::new()));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/predict")
+            .json(&json!({ "uuid": uuid, "step": 5.0 }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 400);
 
-    let auth_api_url = warp::path("auth-api-url").map(move || {
-        let backend_url = env::var("BACKEND_URL").unwrap_or_else(|_| "URL not set".to_string());
-        
-        // La closure capture websocket_url si nécessaire
-        let websocket_url = websocket_url.clone(); 
+        let response = warp::test::request()
+            .method("POST")
+            .path("/predict")
+            .json(&json!({ "uuid": Uuid::new_v4() }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
 
-        let api_urls = ApiUrls {
-            backend_url,
-            websocket_url,
+    #[tokio::test]
+    async fn admin_adds_and_removes_a_planet() {
+        let rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            None,
+        ));
+        let token = Some("secret".to_string());
+        let add =
+            add_planet_route(Arc::clone(&rooms), token.clone()).recover(admin::handle_rejection);
+        let remove =
+            remove_planet_route(Arc::clone(&rooms), token).recover(admin::handle_rejection);
+        let has_planet = |name: &str| {
+            rooms
+                .default_room()
+                .solar_system
+                .read()
+                .positions()
+                .iter()
+                .any(|body| body.name == name)
         };
-        warp::reply::json(&api_urls)
-    });
+        let vulcan = json!({
+            "name": "Vulcan",
+            "distance": 20.0,
+            "orbital_period": 30.0,
+            "radius": 1.0,
+            "mass": 10.0,
+        });
 
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_header("content-type")
-        .allow_methods(["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/planets")
+            .json(&vulcan)
+            .reply(&add)
+            .await;
+        assert_eq!(response.status(), 401);
+        assert!(!has_planet("Vulcan"));
 
-    let routes = auth_api_url.with(cors);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/planets")
+            .header("authorization", "Bearer secret")
+            .json(&vulcan)
+            .reply(&add)
+            .await;
+        assert_eq!(response.status(), 201);
+        assert!(has_planet("Vulcan"));
 
-    tokio::spawn(async move {
-        warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
-    });
+        let response = warp::test::request()
+            .method("POST")
+            .path("/planets")
+            .header("authorization", "Bearer secret")
+            .json(&vulcan)
+            .reply(&add)
+            .await;
+        assert_eq!(response.status(), 409);
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/planets/Vulcan")
+            .header("authorization", "Bearer secret")
+            .reply(&remove)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(!has_planet("Vulcan"));
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/planets/Vulcan")
+            .header("authorization", "Bearer secret")
+            .reply(&remove)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
 
-    let solar_system_clone = Arc::clone(&solar_system);
+    #[tokio::test]
+    async fn kicking_removes_the_ship_from_the_system() {
+        let rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            None,
+        ));
+        let connections = Arc::new(Connections::default());
+        let (room, uuid) = rooms.join("alpha", &mut None).unwrap();
+        let mut directives = connections.register(uuid);
+        let route = kick_route(
+            Arc::clone(&rooms),
+            Arc::clone(&connections),
+            Some("secret".to_string()),
+        );
 
-    let kafka_brokers = env::var("KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string());
-    let kafka_topic = env::var("KAFKA_TOPIC").unwrap_or_else(|_| "planet-positions".to_string());
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/kick/{}", uuid))
+            .header("authorization", "Bearer secret")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(room.solar_system.read().ships.is_empty());
+        assert!(matches!(
+            directives.try_recv(),
+            Ok(Directive::Message(ServerMessage::Kicked))
+        ));
+        assert!(matches!(directives.try_recv(), Ok(Directive::Close)));
 
-    let kafka_producer = KafkaProducer::new(&kafka_brokers, &kafka_topic)
-        .expect("Failed to create Kafka producer");
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/kick/{}", uuid))
+            .header("authorization", "Bearer secret")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
 
+    #[tokio::test]
+    async fn world_route_returns_the_planets_and_the_ships() {
+        let rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            None,
+        ));
+        let (room, uuid) = rooms.join(DEFAULT_ROOM, &mut None).unwrap();
+        room.advance(1, 1.0 / 30.0, 0);
+        let route = world_route(Arc::clone(&rooms));
 
-    let kafka_producer_clone = kafka_producer.clone();
+        let response = warp::test::request()
+            .method("GET")
+            .path("/world")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 200);
+        let world: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(world["tick"], 1);
+        assert!(world["ship"].is_null());
+        // [name, [x, y, z]] as in the frames of the WebSocket
+        let planet = &world["planets"][0];
+        assert_eq!(planet[0], "Mercury");
+        assert_eq!(planet[1].as_array().unwrap().len(), 3);
+        assert!(world["moons"].is_array());
+        let ships = world["ships"].as_array().unwrap();
+        assert_eq!(ships.len(), 1);
+        assert_eq!(ships[0]["uuid"], uuid.to_string());
+        assert!(world["probes"].is_array());
 
-    // Thread to update the solar system
-    thread::spawn(move || {
-        let mut last_update = Instant::now();
+        let response = warp::test::request()
+            .method("GET")
+            .path("/world?room=nowhere")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
 
-        loop {
-            let now = Instant::now();
-            let delta_time = (now - last_update).as_secs_f64();
-            last_update = now;
+    #[tokio::test]
+    async fn planets_route_lists_the_default_planets() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        let route = planets_route(Arc::new(rooms));
 
-            {
-                let mut solar_system = solar_system_clone.lock().unwrap();
-                solar_system.update(delta_time);
-            }
+        let response = warp::test::request()
+            .method("GET")
+            .path("/planets")
+            .reply(&route)
+            .await;
 
-            thread::sleep(Duration::from_millis(1000/30));
-        }
-    });
+        assert_eq!(response.status(), 200);
+        let bodies: Vec<serde_json::Value> = serde_json::from_slice(response.body()).unwrap();
+        let planets: Vec<&serde_json::Value> = bodies
+            .iter()
+            .filter(|body| body["kind"] == "planet")
+            .map(|body| &body["name"])
+            .collect();
+        assert_eq!(planets, ["Mercury", "Venus", "Earth", "Mars", "Jupiter"]);
+        assert!(bodies.iter().all(|body| body["position"].is_array()));
+    }
 
-    let solar_system_clone = Arc::clone(&solar_system);
+    #[tokio::test]
+    async fn health_route_reports_the_uptime() {
+        let route = health_route(Arc::new(Metrics::new()));
 
-    // Thread to send position to Kafka (not the same frequency as the solar system update)
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let response = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .reply(&route)
+            .await;
 
-        loop {
-            interval.tick().await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body, json!({"status": "ok", "uptime_secs": 0}));
+    }
 
-            let positions = {
-                let solar_system = solar_system_clone.lock().unwrap();
-                solar_system.positions()
-            };
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_flag_stops_the_simulation_loop() {
+        let rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            None,
+        ));
+        let room = rooms.default_room();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let simulation = tokio::spawn(simulation_loop(
+            rooms,
+            Arc::new(Connections::default()),
+            broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY).0,
+            Arc::new(Metrics::new()),
+            DEFAULT_TICK_HZ,
+            shutdown,
+        ));
 
-            if let Err(e) = kafka_producer_clone.send_planet_positions(positions).await {
-                eprintln!("Failed to send positions to Kafka: {}", e);
-            }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let ticks = room.latest().tick;
+        assert!(ticks > 0);
 
-            tokio::time::sleep(Duration::from_millis(500)).await;
-        }
-    });
+        shutdown_sender.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), simulation)
+            .await
+            .expect("the simulation loop is still running")
+            .unwrap();
+        let stopped_at = room.latest().tick;
+        assert!(stopped_at <= ticks + 1);
 
-    let websocket_host = env::var("WEBSOCKET_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let websocket_port = env::var("WEBSOCKET_PORT").unwrap_or_else(|_| "3012".to_string());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(room.latest().tick, stopped_at);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_readers_and_the_writer_do_not_deadlock() {
+        let rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            None,
+        ));
+        rooms.join(DEFAULT_ROOM, &mut None).unwrap();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let simulation = tokio::spawn(simulation_loop(
+            Arc::clone(&rooms),
+            Arc::new(Connections::default()),
+            broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY).0,
+            Arc::new(Metrics::new()),
+            DEFAULT_TICK_HZ,
+            shutdown,
+        ));
 
-    let websocket_address = format!("{}:{}", websocket_host, websocket_port);
-    println!("WebSocket server listening on {}", websocket_address);
+        let readers: Vec<_> = (0..32)
+            .map(|_| {
+                let route = ships_route(Arc::clone(&rooms));
+                let room = rooms.default_room();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        let response = warp::test::request().path("/ships").reply(&route).await;
+                        assert_eq!(response.status(), 200);
+                        assert_eq!(room.solar_system.read().ships.len(), 1);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
 
-    ws::listen(&websocket_address, |out| Server {
-        out,
-        solar_system: Arc::clone(&solar_system),
-        last_update: Instant::now(),
-        ship_uuid: Uuid::new_v4(),
-    })
-    .unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            for reader in readers {
+                reader.await.unwrap();
+            }
+        })
+        .await
+        .expect("the readers are stuck");
+        shutdown_sender.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), simulation)
+            .await
+            .expect("the writer is stuck")
+            .unwrap();
+    }
 }
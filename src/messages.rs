@@ -0,0 +1,752 @@
+use crate::probe::Probe;
+use crate::ship::{checked_name, checked_power, TheShip};
+use crate::solar_system::{BodyKind, BodyPosition, Snapshot};
+use crate::vec3::Vec3;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+use warp::ws::Message;
+
+/// Version of the format of the frames, raised on every change a client could mis-parse
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Every frame sent to the clients, discriminated by its "type" field
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First frame of a connection, tells the client which ship is its own
+    /// and the session token to reconnect to it
+    Welcome {
+        uuid: Uuid,
+        session: Uuid,
+        protocol_version: u32,
+    },
+    /// Periodic state of the solar system
+    State { payload: StatePayload },
+    /// Changes since the previous frame, for the clients which asked for deltas
+    Delta { payload: DeltaPayload },
+    /// Answer to a time sync request, `client_time_ms` being the time sent by the client
+    TimeSync {
+        client_time_ms: Option<u64>,
+        server_time_ms: u64,
+        tick: u64,
+    },
+    /// A command of the client was refused
+    Error { reason: String },
+    /// Chat message of a ship of the room, `ts` being the server time it was relayed at
+    Chat { from: String, text: String, ts: u64 },
+    /// The server is stopping, the socket is closed right after
+    Shutdown,
+    /// An admin removed the ship, the socket is closed right after
+    Kicked,
+}
+
+impl ServerMessage {
+    pub fn to_message(&self, encoding: Encoding) -> Message {
+        match encoding {
+            Encoding::Json => Message::text(serde_json::to_string(self).unwrap()),
+            // Named fields, so the structure is the same as the JSON one
+            Encoding::MessagePack => Message::binary(rmp_serde::to_vec_named(self).unwrap()),
+        }
+    }
+}
+
+/// A client which declares the version it speaks must speak the one of the server,
+/// a client which does not is served as before the versions
+pub fn check_protocol_version(declared: Option<u32>) -> Result<(), &'static str> {
+    match declared {
+        Some(version) if version != PROTOCOL_VERSION => Err("unsupported_protocol_version"),
+        _ => Ok(()),
+    }
+}
+
+/// How the frames sent to a client are encoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatePayload {
+    pub tick: u64, // same value for every client, frames of one tick are identical
+    pub server_time_ms: u64,
+    pub world_scale: f64, // `WORLD_SCALE`, factor of every length against the default world
+    pub paused: bool,     // the room stopped by `POST /pause`, the frames repeat the same world
+    pub planets: Vec<(String, Vec3)>,
+    pub moons: Vec<(String, Vec3)>,
+    pub ship: serde_json::Value,
+    pub ships: Vec<TheShip>,
+    pub probes: Vec<Probe>,
+}
+
+impl StatePayload {
+    pub fn new(snapshot: &Snapshot, ship: serde_json::Value) -> Self {
+        Self {
+            tick: snapshot.tick,
+            server_time_ms: snapshot.server_time_ms,
+            world_scale: snapshot.world_scale,
+            paused: snapshot.paused,
+            planets: bodies_of_kind(&snapshot.positions, BodyKind::Planet),
+            moons: bodies_of_kind(&snapshot.positions, BodyKind::Moon),
+            ship,
+            ships: snapshot.ships.clone(),
+            probes: snapshot.probes.clone(),
+        }
+    }
+}
+
+/// Same as the state, but `ships` only holds the ships which changed since the previous frame
+#[derive(Debug, Serialize)]
+pub struct DeltaPayload {
+    pub tick: u64,
+    pub server_time_ms: u64,
+    pub planets: Vec<(String, Vec3)>,
+    pub moons: Vec<(String, Vec3)>,
+    pub ship: serde_json::Value,
+    pub ships: Vec<TheShip>,
+    pub removed: Vec<Uuid>, // ships which left since the previous frame
+    pub probes: Vec<Probe>, // always every probe, they never stop moving
+}
+
+impl DeltaPayload {
+    pub fn new(
+        snapshot: &Snapshot,
+        ship: serde_json::Value,
+        ships: Vec<TheShip>,
+        removed: Vec<Uuid>,
+    ) -> Self {
+        Self {
+            tick: snapshot.tick,
+            server_time_ms: snapshot.server_time_ms,
+            planets: bodies_of_kind(&snapshot.positions, BodyKind::Planet),
+            moons: bodies_of_kind(&snapshot.positions, BodyKind::Moon),
+            ship,
+            ships,
+            removed,
+            probes: snapshot.probes.clone(),
+        }
+    }
+}
+
+fn bodies_of_kind(positions: &[BodyPosition], kind: BodyKind) -> Vec<(String, Vec3)> {
+    positions
+        .iter()
+        .filter(|body| body.kind == kind)
+        .map(|body| (body.name.clone(), Vec3::from(body.position)))
+        .collect()
+}
+
+/// Frame received from a client, the command itself is under "data"
+#[derive(Debug, Deserialize)]
+pub struct ClientMessage {
+    pub data: Option<ClientCommand>,
+}
+
+/// Characters of a chat message, once the control characters are stripped
+const MAX_CHAT_LENGTH: usize = 200;
+
+/// `{"type":"time_sync","client_time_ms":...}`, sent by a client to estimate its
+/// clock offset and round trip time, at any time instead of a command
+#[derive(Debug, Deserialize)]
+pub struct TimeSyncRequest {
+    pub client_time_ms: Option<u64>,
+}
+
+/// Missing keys leave the ship untouched
+#[derive(Debug, Default, Deserialize)]
+pub struct ClientCommand {
+    pub engines: Option<EnginesCommand>,
+    pub rotation: Option<RotationCommand>,
+    pub name: Option<String>,
+    pub autopilot: Option<AutopilotCommand>,
+    pub respawn: Option<bool>, // back to the spawn point, for a ship stuck or lost
+    pub all_stop: Option<bool>, // every engine off and the autopilot disengaged, at once
+    pub seq: Option<u64>,      // increasing from 1, echoed in `last_seq` once applied
+    pub launch_probe: Option<bool>, // replaces the previous probe of the ship
+    pub orbit: Option<OrbitCommand>,
+    pub chat: Option<String>, // relayed to every ship of the room, not applied to the ship
+    pub client_time_ms: Option<u64>, // sent at, the command then waits for the next tick
+}
+
+/// `{"target":"Jupiter","radius":20.0}` puts the ship on a circular orbit around the body
+#[derive(Debug, Deserialize)]
+pub struct OrbitCommand {
+    pub target: String,
+    pub radius: f64, // from the center of the body
+}
+
+/// `{"target":"Earth"}` engages the autopilot, `{"target":null}` disengages it
+#[derive(Debug, Default, Deserialize)]
+pub struct AutopilotCommand {
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EnginesCommand {
+    pub power: Option<f64>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub front: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub back: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub left: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub right: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub up: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub down: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub boost: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RotationCommand {
+    pub power: Option<f64>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub left: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub right: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub up: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub down: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub roll_left: Option<bool>,
+    #[serde(default, deserialize_with = "lenient_flag")]
+    pub roll_right: Option<bool>,
+}
+
+/// A flag that is not a boolean is ignored (with a warning) instead of rejecting the whole command
+fn lenient_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(flag) => Ok(Some(flag)),
+        serde_json::Value::Null => Ok(None),
+        other => {
+            eprintln!("Ignoring engine flag with a non-boolean value: {}", other);
+            Ok(None)
+        }
+    }
+}
+
+impl ClientCommand {
+    /// Chat message to relay, without its control characters
+    /// Returns the reason of the refusal when it is empty or too long
+    pub fn chat_text(&self) -> Option<Result<String, &'static str>> {
+        let text = self.chat.as_ref()?;
+        let sanitized: String = text.chars().filter(|c| !c.is_control()).collect();
+        let sanitized = sanitized.trim();
+
+        Some(if sanitized.is_empty() {
+            Err("empty_chat")
+        } else if sanitized.chars().count() > MAX_CHAT_LENGTH {
+            Err("chat_too_long")
+        } else {
+            Ok(sanitized.to_string())
+        })
+    }
+
+    /// Returns the reason of the refusal when a part of the command is invalid
+    pub fn apply(&self, ship: &mut TheShip) -> Result<(), &'static str> {
+        // Late or duplicated, a newer command was already applied
+        if let Some(seq) = self.seq {
+            if seq <= ship.last_seq {
+                return Ok(());
+            }
+        }
+
+        // Every part is checked before any is applied, a refused command leaves the ship as it was
+        let engine_power = self.engines.as_ref().and_then(|engines| engines.power);
+        let engine_power = engine_power.map(checked_power).transpose()?;
+        let rotation_power = self.rotation.as_ref().and_then(|rotation| rotation.power);
+        let rotation_power = rotation_power.map(checked_power).transpose()?;
+        let name = self.name.as_deref().map(checked_name).transpose()?;
+
+        // First, so that the rest of the command applies to the respawned or stopped ship
+        if self.respawn == Some(true) {
+            ship.respawn();
+        }
+        if self.all_stop == Some(true) {
+            ship.release_controls();
+            ship.autopilot = None;
+        }
+
+        if let Some(engines) = &self.engines {
+            if let Some(power) = engine_power {
+                ship.engines.power = power;
+            }
+
+            let set = |flag: &mut bool, value: Option<bool>| *flag = value.unwrap_or(*flag);
+            set(&mut ship.engines.front, engines.front);
+            set(&mut ship.engines.back, engines.back);
+            set(&mut ship.engines.left, engines.left);
+            set(&mut ship.engines.right, engines.right);
+            set(&mut ship.engines.up, engines.up);
+            set(&mut ship.engines.down, engines.down);
+            set(&mut ship.engines.boost, engines.boost);
+
+            // Taking the controls back
+            let manual = [
+                engines.front,
+                engines.back,
+                engines.left,
+                engines.right,
+                engines.up,
+                engines.down,
+            ];
+            if manual.contains(&Some(true)) {
+                ship.autopilot = None;
+            }
+        }
+
+        if let Some(rotation) = &self.rotation {
+            if let Some(power) = rotation_power {
+                ship.rotation_engines.power = power;
+            }
+
+            let set = |flag: &mut bool, value: Option<bool>| *flag = value.unwrap_or(*flag);
+            set(&mut ship.rotation_engines.left, rotation.left);
+            set(&mut ship.rotation_engines.right, rotation.right);
+            set(&mut ship.rotation_engines.up, rotation.up);
+            set(&mut ship.rotation_engines.down, rotation.down);
+            set(&mut ship.rotation_engines.roll_left, rotation.roll_left);
+            set(&mut ship.rotation_engines.roll_right, rotation.roll_right);
+        }
+
+        if let Some(name) = name {
+            ship.name = name;
+        }
+
+        if let Some(orbit) = &self.orbit {
+            ship.orbit_request = Some((orbit.target.clone(), orbit.radius));
+        }
+
+        if self.launch_probe == Some(true) {
+            ship.probe_requested = true;
+        }
+
+        if let Some(autopilot) = &self.autopilot {
+            match &autopilot.target {
+                Some(target) => ship.engage_autopilot(target),
+                None => ship.autopilot = None,
+            }
+        }
+
+        // Only once applied, the client must not take a refused command for an applied one
+        if let Some(seq) = self.seq {
+            ship.last_seq = seq;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::{Engines, RotationEngines};
+    use crate::solar_system::{BodyPosition, SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn to_json(message: ServerMessage) -> serde_json::Value {
+        serde_json::from_str(message.to_message(Encoding::Json).to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn welcome_frame_carries_the_ship_uuid() {
+        let (uuid, session) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(
+            to_json(ServerMessage::Welcome {
+                uuid,
+                session,
+                protocol_version: PROTOCOL_VERSION,
+            }),
+            json!({
+                "type": "welcome",
+                "uuid": uuid.to_string(),
+                "session": session.to_string(),
+                "protocol_version": PROTOCOL_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn state_frame_wraps_the_payload() {
+        let snapshot = Snapshot {
+            tick: 7,
+            server_time_ms: 233,
+            positions: vec![
+                BodyPosition {
+                    name: "Earth".to_string(),
+                    kind: BodyKind::Planet,
+                    position: (1.0, 2.0, 3.0),
+                },
+                BodyPosition {
+                    name: "Moon".to_string(),
+                    kind: BodyKind::Moon,
+                    position: (4.0, 5.0, 6.0),
+                },
+            ],
+            ships: Vec::new(),
+            probes: Vec::new(),
+            world_scale: 2.0,
+            paused: false,
+            frame: 0,
+        };
+        let payload = StatePayload::new(&snapshot, json!({"fuel": 1.0}));
+        assert_eq!(
+            to_json(ServerMessage::State { payload }),
+            json!({
+                "type": "state",
+                "payload": {
+                    "tick": 7,
+                    "server_time_ms": 233,
+                    "world_scale": 2.0,
+                    "paused": false,
+                    "planets": [["Earth", [1.0, 2.0, 3.0]]],
+                    "moons": [["Moon", [4.0, 5.0, 6.0]]],
+                    "ship": {"fuel": 1.0},
+                    "ships": [],
+                    "probes": [],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn error_frame_has_a_reason() {
+        let reason = "invalid_command".to_string();
+        assert_eq!(
+            to_json(ServerMessage::Error { reason }),
+            json!({"type": "error", "reason": "invalid_command"})
+        );
+    }
+
+    #[test]
+    fn malformed_commands_leave_the_ship_unchanged() {
+        let payloads = [
+            r#"{}"#,
+            r#"[1, 2]"#,
+            r#"{"data": 42}"#,
+            r#"{"data": {"engines": {}}}"#,
+            r#"{"data": {"engines": "on"}}"#,
+            r#"{"data": {"engines": {"front": "yes", "back": 1}}}"#,
+            r#"{"data": {"rotation": {"left": null, "roll_right": [true]}}}"#,
+        ];
+
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let before = ship.to_json();
+        for payload in payloads {
+            if let Ok(ClientMessage {
+                data: Some(command),
+            }) = serde_json::from_str::<ClientMessage>(payload)
+            {
+                command.apply(&mut ship).unwrap();
+            }
+            assert_eq!(ship.to_json(), before, "{}", payload);
+        }
+
+        let command: ClientMessage =
+            serde_json::from_str(r#"{"data": {"engines": {"front": true, "back": "no"}}}"#)
+                .unwrap();
+        command.data.unwrap().apply(&mut ship).unwrap();
+        assert!(ship.engines.front);
+        assert!(!ship.engines.back);
+    }
+
+    #[test]
+    fn all_stop_turns_every_engine_off() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let engines: ClientMessage = serde_json::from_str(
+            r#"{"data": {
+                "engines": {"front": true, "left": true, "down": true, "boost": true},
+                "rotation": {"left": true, "up": true, "roll_right": true}
+            }}"#,
+        )
+        .unwrap();
+        engines.data.unwrap().apply(&mut ship).unwrap();
+        assert!(ship.engines.front && ship.rotation_engines.roll_right);
+
+        let all_stop: ClientMessage =
+            serde_json::from_str(r#"{"data": {"all_stop": true}}"#).unwrap();
+        all_stop.data.unwrap().apply(&mut ship).unwrap();
+
+        let Engines {
+            front,
+            back,
+            left,
+            right,
+            up,
+            down,
+            boost,
+            ..
+        } = ship.engines;
+        assert_eq!([front, back, left, right, up, down, boost], [false; 7]);
+        let RotationEngines {
+            left,
+            right,
+            up,
+            down,
+            roll_left,
+            roll_right,
+            ..
+        } = ship.rotation_engines;
+        assert_eq!([left, right, up, down, roll_left, roll_right], [false; 6]);
+    }
+
+    #[test]
+    fn power_is_set_within_its_bounds() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let apply = |ship: &mut TheShip, payload: &str| {
+            let message: ClientMessage = serde_json::from_str(payload).unwrap();
+            message.data.unwrap().apply(ship)
+        };
+
+        apply(
+            &mut ship,
+            r#"{"data": {"engines": {"power": 2.0}, "rotation": {"power": 0.8}}}"#,
+        )
+        .unwrap();
+        assert_eq!(ship.engines.power, 2.0);
+        assert_eq!(ship.rotation_engines.power, 0.8);
+
+        for payload in [
+            r#"{"data": {"engines": {"power": 5.5}}}"#,
+            r#"{"data": {"engines": {"power": 0.0}}}"#,
+            r#"{"data": {"rotation": {"power": -1.0}}}"#,
+        ] {
+            assert_eq!(
+                apply(&mut ship, payload),
+                Err("invalid_power"),
+                "{}",
+                payload
+            );
+        }
+        assert_eq!(ship.engines.power, 2.0);
+        assert_eq!(ship.rotation_engines.power, 0.8);
+    }
+
+    #[test]
+    fn refused_command_leaves_the_ship_unchanged() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let name = ship.name.clone();
+        let apply = |ship: &mut TheShip, payload: &str| {
+            let message: ClientMessage = serde_json::from_str(payload).unwrap();
+            message.data.unwrap().apply(ship)
+        };
+
+        // The invalid part comes after the ones which are valid
+        assert_eq!(
+            apply(
+                &mut ship,
+                r#"{"data": {"respawn": true, "engines": {"front": true, "power": 2.0}, "name": "Esker", "rotation": {"power": 9.0}}}"#,
+            ),
+            Err("invalid_power")
+        );
+        assert_eq!(
+            apply(
+                &mut ship,
+                r#"{"data": {"engines": {"back": true, "power": 2.0}, "name": ""}}"#,
+            ),
+            Err("empty_name")
+        );
+
+        assert!(!ship.engines.front);
+        assert!(!ship.engines.back);
+        assert_eq!(ship.engines.power, 1.0);
+        assert_eq!(ship.name, name);
+    }
+
+    #[test]
+    fn stale_sequence_numbers_are_ignored() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let apply = |ship: &mut TheShip, payload: &str| {
+            let message: ClientMessage = serde_json::from_str(payload).unwrap();
+            message.data.unwrap().apply(ship).unwrap();
+        };
+
+        apply(
+            &mut ship,
+            r#"{"data": {"seq": 2, "engines": {"front": true}}}"#,
+        );
+        // A duplicate, then one sent before but arriving late
+        apply(
+            &mut ship,
+            r#"{"data": {"seq": 2, "engines": {"front": false}}}"#,
+        );
+        apply(
+            &mut ship,
+            r#"{"data": {"seq": 1, "engines": {"back": true}}}"#,
+        );
+        assert!(ship.engines.front);
+        assert!(!ship.engines.back);
+        assert_eq!(ship.last_seq, 2);
+
+        apply(
+            &mut ship,
+            r#"{"data": {"seq": 5, "engines": {"front": false}}}"#,
+        );
+        assert!(!ship.engines.front);
+        assert_eq!(ship.to_json()["last_seq"], 5);
+    }
+
+    #[test]
+    fn refused_command_does_not_advance_the_sequence() {
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let apply = |ship: &mut TheShip, payload: &str| {
+            let message: ClientMessage = serde_json::from_str(payload).unwrap();
+            message.data.unwrap().apply(ship)
+        };
+
+        apply(&mut ship, r#"{"data": {"seq": 1, "name": "Esker"}}"#).unwrap();
+        assert_eq!(
+            apply(
+                &mut ship,
+                r#"{"data": {"seq": 2, "engines": {"power": 9.0}}}"#
+            ),
+            Err("invalid_power")
+        );
+        assert_eq!(ship.last_seq, 1);
+
+        // The same seq sent again, fixed, is not taken for a duplicate
+        apply(
+            &mut ship,
+            r#"{"data": {"seq": 2, "engines": {"power": 2.0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(ship.engines.power, 2.0);
+        assert_eq!(ship.last_seq, 2);
+    }
+
+    #[test]
+    fn respawn_resets_the_motion_of_the_ship() {
+        let uuid = Uuid::new_v4();
+        let spawn = TheShip::new_with_uuid(uuid);
+        let mut ship = TheShip::new_with_uuid(uuid);
+        ship.name = "Feldspar".to_string();
+        ship.position = (120.0, -40.0, 3.0);
+        ship.speed = (5.0, 6.0, 7.0);
+        ship.direction = (0.0, 1.0, 0.0);
+        ship.angle = 1.2;
+        ship.pitch = -0.4;
+
+        let command: ClientMessage =
+            serde_json::from_str(r#"{"data": {"respawn": true}}"#).unwrap();
+        command.data.unwrap().apply(&mut ship).unwrap();
+
+        assert_eq!(ship.uuid, uuid);
+        assert_eq!(ship.name, "Feldspar");
+        assert_eq!(ship.position, spawn.position);
+        assert_eq!(ship.speed, spawn.speed);
+        assert_eq!(ship.direction, spawn.direction);
+        assert_eq!(ship.angle, spawn.angle);
+        assert_eq!(ship.pitch, spawn.pitch);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct DecodedFrame {
+        r#type: String,
+        payload: DecodedState,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct DecodedState {
+        tick: u64,
+        planets: Vec<(String, (f64, f64, f64))>,
+        moons: Vec<(String, (f64, f64, f64))>,
+        ships: Vec<DecodedShip>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct DecodedShip {
+        uuid: Uuid,
+        name: String,
+        position: (f64, f64, f64),
+        speed: (f64, f64, f64),
+        fuel: f64,
+    }
+
+    #[test]
+    fn state_frame_round_trips_through_message_pack() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        ship.engines.front = true;
+        solar_system.add_ship(Arc::new(Mutex::new(ship)));
+        solar_system.advance(1.0 / 30.0);
+        let snapshot = solar_system.snapshot(0);
+        let ship = &snapshot.ships[0];
+
+        let message = ServerMessage::State {
+            payload: StatePayload::new(&snapshot, ship.to_json()),
+        }
+        .to_message(Encoding::MessagePack);
+        assert!(message.is_binary());
+        let decoded: DecodedFrame = rmp_serde::from_slice(message.as_bytes()).unwrap();
+
+        let body = |kind: BodyKind| {
+            snapshot
+                .positions
+                .iter()
+                .filter(|body| body.kind == kind)
+                .map(|body| (body.name.clone(), body.position))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            decoded,
+            DecodedFrame {
+                r#type: "state".to_string(),
+                payload: DecodedState {
+                    tick: 1,
+                    planets: body(BodyKind::Planet),
+                    moons: body(BodyKind::Moon),
+                    ships: vec![DecodedShip {
+                        uuid: ship.uuid,
+                        name: ship.name.clone(),
+                        position: ship.position,
+                        speed: ship.speed,
+                        fuel: ship.fuel,
+                    }],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn chat_text_is_stripped_and_length_limited() {
+        let chat = |text: &str| {
+            let command = ClientCommand {
+                chat: Some(text.to_string()),
+                ..Default::default()
+            };
+            command.chat_text().unwrap()
+        };
+
+        assert_eq!(chat(" hello\u{7}\n"), Ok("hello".to_string()));
+        assert_eq!(chat("\t\r"), Err("empty_chat"));
+        assert!(chat(&"é".repeat(MAX_CHAT_LENGTH)).is_ok());
+        assert_eq!(chat(&"a".repeat(MAX_CHAT_LENGTH + 1)), Err("chat_too_long"));
+        assert_eq!(ClientCommand::default().chat_text(), None);
+    }
+
+    #[test]
+    fn unsupported_protocol_version_is_rejected() {
+        assert_eq!(
+            check_protocol_version(Some(PROTOCOL_VERSION + 1)),
+            Err("unsupported_protocol_version")
+        );
+        assert_eq!(
+            check_protocol_version(Some(0)),
+            Err("unsupported_protocol_version")
+        );
+    }
+
+    #[test]
+    fn current_or_undeclared_protocol_version_is_accepted() {
+        assert_eq!(check_protocol_version(Some(PROTOCOL_VERSION)), Ok(()));
+        assert_eq!(check_protocol_version(None), Ok(()));
+    }
+}
@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counters shared by the simulation, the WebSocket handlers and the HTTP API
+pub struct Metrics {
+    started_at: Instant,
+    connected_clients: AtomicUsize,
+    messages_processed: AtomicU64,
+    ticks: AtomicU64,
+    tick_rate: AtomicU64, // f64 bits, simulation steps per second
+    kafka_retries: AtomicU64,
+    kafka_failures: AtomicU64, // records dropped once out of retries
+}
+
+#[derive(Serialize)]
+pub struct MetricsReport {
+    pub uptime_secs: u64,
+    pub connected_clients: usize,
+    pub messages_processed: u64,
+    pub ticks: u64,
+    pub tick_rate: f64,
+    pub kafka_retries: u64,
+    pub kafka_failures: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            connected_clients: AtomicUsize::new(0),
+            messages_processed: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            tick_rate: AtomicU64::new(0.0_f64.to_bits()),
+            kafka_retries: AtomicU64::new(0),
+            kafka_failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn message_processed(&self) {
+        self.messages_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn kafka_retry(&self) {
+        self.kafka_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a record which could not be delivered to Kafka
+    pub fn kafka_failure(&self) {
+        self.kafka_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a simulation step that lasted `delta_time` seconds
+    pub fn record_tick(&self, delta_time: f64) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        if delta_time > 0.0 {
+            self.tick_rate
+                .store((1.0 / delta_time).to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn report(&self) -> MetricsReport {
+        MetricsReport {
+            uptime_secs: self.uptime_secs(),
+            connected_clients: self.connected_clients.load(Ordering::Relaxed),
+            messages_processed: self.messages_processed.load(Ordering::Relaxed),
+            ticks: self.ticks.load(Ordering::Relaxed),
+            tick_rate: f64::from_bits(self.tick_rate.load(Ordering::Relaxed)),
+            kafka_retries: self.kafka_retries.load(Ordering::Relaxed),
+            kafka_failures: self.kafka_failures.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::solar_system::Planet;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+
+/// One planet (or moon) of a `PLANETS_CONFIG` file, in the units of the simulation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanetConfig {
+    pub name: String,
+    pub distance: f64, // semi-major axis, from the sun or from the planet of a moon
+    pub orbital_period: f64, // seconds
+    #[serde(default)]
+    pub eccentricity: f64,
+    #[serde(default)]
+    pub inclination: f64, // degrees
+    pub radius: f64,
+    pub mass: f64,
+    #[serde(default)]
+    pub moons: Vec<PlanetConfig>,
+}
+
+/// Why a `PLANETS_CONFIG` file was refused
+#[derive(Debug)]
+pub enum PlanetsConfigError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+    Empty,
+    Invalid { name: String, reason: &'static str },
+}
+
+impl fmt::Display for PlanetsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanetsConfigError::Read(e) => write!(f, "cannot read the file: {}", e),
+            PlanetsConfigError::Parse(e) => write!(f, "malformed JSON: {}", e),
+            PlanetsConfigError::Empty => write!(f, "no planet"),
+            PlanetsConfigError::Invalid { name, reason } => {
+                write!(f, "planet \"{}\": {}", name, reason)
+            }
+        }
+    }
+}
+
+impl PlanetConfig {
+    fn check(&self) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("empty name");
+        }
+        if !(self.distance.is_finite() && self.distance > 0.0) {
+            return Err("distance must be positive");
+        }
+        if !(self.orbital_period.is_finite() && self.orbital_period > 0.0) {
+            return Err("orbital_period must be positive");
+        }
+        if !(0.0..1.0).contains(&self.eccentricity) {
+            return Err("eccentricity must be at least 0 and below 1");
+        }
+        if !self.inclination.is_finite() {
+            return Err("inclination must be a number of degrees");
+        }
+        if !(self.radius.is_finite() && self.radius > 0.0) {
+            return Err("radius must be positive");
+        }
+        if !(self.mass.is_finite() && self.mass >= 0.0) {
+            return Err("mass cannot be negative");
+        }
+        Ok(())
+    }
+
+    fn to_planet(&self) -> Planet {
+        Planet::new(
+            &self.name,
+            self.distance,
+            self.eccentricity,
+            self.inclination,
+            self.orbital_period,
+            self.mass,
+            self.radius,
+        )
+        .with_moons(self.moons.iter().map(PlanetConfig::to_planet).collect())
+    }
+}
+
+/// Read the planets of the JSON file at `path`, an array of `PlanetConfig`
+pub fn load_planets(path: &str) -> Result<Vec<Planet>, PlanetsConfigError> {
+    let content = std::fs::read_to_string(path).map_err(PlanetsConfigError::Read)?;
+    let configs: Vec<PlanetConfig> =
+        serde_json::from_str(&content).map_err(PlanetsConfigError::Parse)?;
+    if configs.is_empty() {
+        return Err(PlanetsConfigError::Empty);
+    }
+    build_planets(&configs)
+}
+
+/// Check every planet and moon, then build them
+pub fn build_planets(configs: &[PlanetConfig]) -> Result<Vec<Planet>, PlanetsConfigError> {
+    // The names identify the bodies for the autopilot, the landings and Kafka
+    let mut names = HashSet::new();
+    let mut pending: Vec<&PlanetConfig> = configs.iter().collect();
+    while let Some(config) = pending.pop() {
+        let invalid = |reason| PlanetsConfigError::Invalid {
+            name: config.name.clone(),
+            reason,
+        };
+        config.check().map_err(invalid)?;
+        if !names.insert(config.name.as_str()) {
+            return Err(invalid("duplicate name"));
+        }
+        pending.extend(&config.moons);
+    }
+
+    Ok(configs.iter().map(PlanetConfig::to_planet).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solar_system::{SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+
+    /// Write `content` to a file of its own in the temporary directory
+    fn config_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn two_planet_config_replaces_the_default_planets() {
+        let path = config_file(
+            "two-planets",
+            r#"[
+                {"name": "Alpha", "distance": 50.0, "orbital_period": 60.0, "radius": 3.0, "mass": 100.0},
+                {"name": "Beta", "distance": 80.0, "orbital_period": 120.0, "eccentricity": 0.1,
+                 "radius": 5.0, "mass": 200.0}
+            ]"#,
+        );
+        let planets = load_planets(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).with_planets(planets);
+
+        let positions = solar_system.positions();
+        let names: Vec<&str> = positions.iter().map(|body| body.name.as_str()).collect();
+        assert_eq!(names, ["Alpha", "Beta"]);
+        let alpha = positions.iter().find(|body| body.name == "Alpha").unwrap();
+        let (x, y, z) = alpha.position;
+        assert!(((x * x + y * y + z * z).sqrt() - 50.0).abs() < 1e-6);
+        assert_eq!(solar_system.body_radius("Beta"), Some(5.0));
+        assert!(!solar_system.has_body("Earth"));
+    }
+
+    #[test]
+    fn malformed_entries_are_refused() {
+        let cases = [
+            ("not-json", "[{"),
+            ("empty", "[]"),
+            (
+                "negative-radius",
+                r#"[{"name": "Alpha", "distance": 50.0, "orbital_period": 60.0, "radius": -3.0, "mass": 1.0}]"#,
+            ),
+            (
+                "duplicate",
+                r#"[{"name": "Alpha", "distance": 50.0, "orbital_period": 60.0, "radius": 3.0, "mass": 1.0},
+                    {"name": "Alpha", "distance": 80.0, "orbital_period": 90.0, "radius": 3.0, "mass": 1.0}]"#,
+            ),
+        ];
+        for (name, content) in cases {
+            let path = config_file(name, content);
+            let result = load_planets(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "{} was accepted", name);
+        }
+
+        assert!(matches!(
+            load_planets("/nonexistent/planets.json"),
+            Err(PlanetsConfigError::Read(_))
+        ));
+    }
+}
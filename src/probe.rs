@@ -0,0 +1,59 @@
+use crate::ship::{Attractor, TheShip};
+use crate::vec3;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Speed given to a probe along the nose of its ship, on top of the speed of the ship
+const LAUNCH_SPEED: f64 = 30.0;
+/// Seconds a probe flies before it is lost
+const LIFETIME: f64 = 20.0;
+
+/// Scout probe, drifting under gravity until it hits a planet, a moon or runs out of time
+#[derive(Debug, Clone, Serialize)]
+pub struct Probe {
+    pub owner: Uuid, // ship which launched it, a ship has one probe at most
+    #[serde(serialize_with = "vec3::serialize")]
+    pub position: (f64, f64, f64),
+    #[serde(serialize_with = "vec3::serialize")]
+    pub speed: (f64, f64, f64),
+    pub expires_in: f64, // seconds left
+}
+
+impl Probe {
+    /// Shot from the ship along its nose
+    pub fn launch(ship: &TheShip) -> Self {
+        Self {
+            owner: ship.uuid,
+            position: ship.position,
+            speed: (
+                ship.speed.0 + ship.direction.0 * LAUNCH_SPEED,
+                ship.speed.1 + ship.direction.1 * LAUNCH_SPEED,
+                ship.speed.2 + ship.direction.2 * LAUNCH_SPEED,
+            ),
+            expires_in: LIFETIME,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        delta_time: f64,
+        attractors: &[Attractor],
+        gravitational_constant: f64,
+    ) {
+        for attractor in attractors {
+            let pull = attractor.pull(self.position, gravitational_constant);
+            self.speed.0 += pull.0 * delta_time;
+            self.speed.1 += pull.1 * delta_time;
+            self.speed.2 += pull.2 * delta_time;
+        }
+
+        self.position.0 += self.speed.0 * delta_time;
+        self.position.1 += self.speed.1 * delta_time;
+        self.position.2 += self.speed.2 * delta_time;
+        self.expires_in -= delta_time;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_in <= 0.0
+    }
+}
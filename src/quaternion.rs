@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::ops::Mul;
+
+/// Unit quaternion representing a rotation in 3D
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    /// Rotation of `angle` radians around `axis` (expected to be normalized)
+    pub fn from_axis_angle(axis: (f64, f64, f64), angle: f64) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self {
+            w: cos,
+            x: axis.0 * sin,
+            y: axis.1 * sin,
+            z: axis.2 * sin,
+        }
+    }
+
+    pub fn norm(&self) -> f64 {
+        (self.w.powi(2) + self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
+    }
+
+    /// No rotation at all
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// Back to unit length, to remove the drift accumulated by many multiplications
+    /// A degenerate quaternion (zero or not finite) becomes the identity instead of NaN,
+    /// which would spread to the whole physics of the ship
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        if !norm.is_finite() || norm < f64::EPSILON {
+            return Self::IDENTITY;
+        }
+
+        Self {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm,
+        }
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// Apply the rotation to a vector
+    pub fn rotate_vector(&self, vector: (f64, f64, f64)) -> (f64, f64, f64) {
+        let pure = Quaternion {
+            w: 0.0,
+            x: vector.0,
+            y: vector.1,
+            z: vector.2,
+        };
+        let rotated = *self * pure * self.conjugate();
+        (rotated.x, rotated.y, rotated.z)
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// Hamilton product, `a * b` applies `b` first then `a`
+    fn mul(self, other: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
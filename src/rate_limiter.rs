@@ -0,0 +1,75 @@
+use std::time::Instant;
+
+/// Token bucket: holds up to `capacity` tokens, refilled continuously at `capacity` per second
+pub struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            capacity: per_second as f64,
+            tokens: per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is available, returns false when the caller is over the limit
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = (now - self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::ClientMessage;
+    use crate::ship::TheShip;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn burst_is_capped_to_the_limit() {
+        let mut limiter = RateLimiter::new(120);
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        let now = limiter.last_refill;
+
+        // Every frame flips the front engine, so each processed frame changes the ship
+        let mut mutations = 0;
+        for index in 0..1000 {
+            let before = ship.engines.front;
+            let front = index % 2 == 0;
+            let frame = format!(r#"{{"data": {{"engines": {{"front": {}}}}}}}"#, front);
+            if limiter.try_acquire_at(now) {
+                let command = serde_json::from_str::<ClientMessage>(&frame).unwrap();
+                command.data.unwrap().apply(&mut ship).unwrap();
+            }
+            if ship.engines.front != before {
+                mutations += 1;
+            }
+        }
+        assert_eq!(mutations, 120);
+
+        // Two seconds later the bucket is full again, but never above its capacity
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            (0..1000).filter(|_| limiter.try_acquire_at(later)).count(),
+            120
+        );
+    }
+}
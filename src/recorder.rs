@@ -0,0 +1,93 @@
+use crate::kafka_encoder::WorldSnapshot;
+use crate::kafka_producer;
+use crate::solar_system::Snapshot;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{broadcast, watch};
+
+/// The buffered snapshots are written to the file at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write every snapshot of every room to the file at `path`, one `WorldSnapshot` in JSON
+/// per line, until the server stops or the snapshots end. Run as a task of its own, the
+/// simulation never waits for the disk
+pub async fn record(
+    path: &str,
+    mut snapshots: broadcast::Receiver<(String, Arc<Snapshot>)>,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path).await?);
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = flush.tick() => file.flush().await?,
+            received = snapshots.recv() => match received {
+                Ok((room, snapshot)) => {
+                    let world = kafka_producer::world_snapshot(&room, &snapshot);
+                    let mut line = serde_json::to_vec(&world).map_err(io::Error::from)?;
+                    line.push(b'\n');
+                    file.write_all(&line).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("The recorder is too slow, {} world snapshots skipped", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    file.flush().await
+}
+
+/// The snapshots of a file written by `record`, in the order they were recorded
+pub fn read_recording(path: &str) -> io::Result<Vec<WorldSnapshot>> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut worlds = Vec::new();
+    for line in file.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            worlds.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(worlds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solar_system::{SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+
+    #[tokio::test]
+    async fn recorded_ticks_are_read_back() {
+        let path = std::env::temp_dir().join(format!("recording-{}.jsonl", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let (sender, receiver) = broadcast::channel(16);
+        let (_shutdown, shutdown_receiver) = watch::channel(false);
+        let recorder = tokio::spawn({
+            let path = path.clone();
+            async move { record(&path, receiver, shutdown_receiver).await }
+        });
+
+        let mut world = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        for _ in 0..5 {
+            world.advance(1.0 / 30.0);
+            sender
+                .send(("default".to_string(), Arc::new(world.snapshot(0))))
+                .unwrap();
+        }
+        // The end of the snapshots stops the recorder, which flushes the file
+        drop(sender);
+        recorder.await.unwrap().unwrap();
+
+        let recording = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ticks: Vec<u64> = recording.iter().map(|world| world.tick).collect();
+        assert_eq!(ticks, vec![1, 2, 3, 4, 5]);
+        assert!(recording.iter().all(|world| world.room == "default"));
+        assert_eq!(recording[0].planets.len(), world.positions().len());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::kafka_encoder::WorldSnapshot;
+use crate::ship::TheShip;
+use crate::solar_system::{BodyKind, BodyPosition, Snapshot};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Hand the recorded snapshots to `publish` with the name of their room, at the pace they
+/// were recorded at. Over again from the start when `looped`, until the server stops
+pub async fn play(
+    recording: &[WorldSnapshot],
+    looped: bool,
+    mut shutdown: watch::Receiver<bool>,
+    mut publish: impl FnMut(&str, Snapshot),
+) {
+    let Some(first) = recording.first() else {
+        return;
+    };
+
+    loop {
+        let start = Instant::now();
+        for world in recording {
+            let offset = Duration::from_millis(world.timestamp.saturating_sub(first.timestamp));
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = tokio::time::sleep_until(start + offset) => {}
+            }
+            publish(&world.room, to_snapshot(world, offset.as_millis() as u64));
+        }
+        if !looped {
+            return;
+        }
+    }
+}
+
+/// The recorded world as a snapshot of the simulation. Only the name, the position and the
+/// speed of the ships are recorded, the rest of each ship is the one of a new ship
+fn to_snapshot(world: &WorldSnapshot, server_time_ms: u64) -> Snapshot {
+    Snapshot {
+        tick: world.tick,
+        server_time_ms,
+        positions: world
+            .planets
+            .iter()
+            .filter_map(|planet| {
+                Some(BodyPosition {
+                    name: planet.name.clone(),
+                    kind: BodyKind::parse(&planet.type_object)?,
+                    position: (planet.x, planet.y, planet.z),
+                })
+            })
+            .collect(),
+        ships: world
+            .ships
+            .iter()
+            .filter_map(|recorded| {
+                let mut ship = TheShip::new_with_uuid(Uuid::parse_str(&recorded.uuid).ok()?);
+                ship.name = recorded.name.clone();
+                ship.position = (recorded.x, recorded.y, recorded.z);
+                ship.prev_position = ship.position;
+                ship.speed = recorded.speed;
+                Some(ship)
+            })
+            .collect(),
+        probes: Vec::new(),
+        world_scale: 1.0,
+        paused: false,
+        frame: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world(tick: u64, timestamp: u64) -> WorldSnapshot {
+        WorldSnapshot {
+            room: "default".to_string(),
+            tick,
+            timestamp,
+            planets: Vec::new(),
+            ships: Vec::new(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn playback_follows_the_order_and_the_pace_of_the_recording() {
+        // 25 frames per second
+        let recording: Vec<WorldSnapshot> =
+            (0..5).map(|tick| world(tick, 1000 + 40 * tick)).collect();
+        let (_shutdown, shutdown_receiver) = watch::channel(false);
+
+        let start = Instant::now();
+        let mut published = Vec::new();
+        play(&recording, false, shutdown_receiver, |room, snapshot| {
+            assert_eq!(room, "default");
+            published.push((snapshot.tick, start.elapsed()));
+        })
+        .await;
+
+        // The clock of tokio only moves on to the next frame
+        let expected: Vec<(u64, Duration)> = (0..5)
+            .map(|tick| (tick, Duration::from_millis(40 * tick)))
+            .collect();
+        assert_eq!(published, expected);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::messages::ClientCommand;
+use crate::ship::TheShip;
+use crate::solar_system::{Planet, SharedSnapshot, Snapshot, SolarSystem};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Room of the clients which do not ask for one
+pub const DEFAULT_ROOM: &str = "default";
+const MAX_ROOM_NAME_LENGTH: usize = 32;
+/// Snapshots a slow client can fall behind before it starts skipping some
+pub const SNAPSHOT_CHANNEL_CAPACITY: usize = 16;
+
+/// A solar system of its own, with the ships of the clients which joined it
+pub struct Room {
+    pub name: String,
+    pub solar_system: RwLock<SolarSystem>,
+    pub snapshot: SharedSnapshot, // latest one, replaced by `advance`
+    pub snapshots: broadcast::Sender<Arc<Snapshot>>,
+    frames: AtomicU64, // snapshots published, the `frame` of the next one
+    commands: Mutex<Vec<BufferedCommand>>, // until the next tick
+}
+
+/// Command of a client which gave the time it sent it at
+struct BufferedCommand {
+    ship_uuid: Uuid,
+    client_time_ms: u64, // on the clock of the client
+    command: ClientCommand,
+}
+
+impl Room {
+    fn new(name: &str, solar_system: SolarSystem, server_time_ms: u64) -> Self {
+        let snapshot = Arc::new(RwLock::new(Arc::new(solar_system.snapshot(server_time_ms))));
+        let (snapshots, _) = broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY);
+
+        Self {
+            name: name.to_string(),
+            solar_system: RwLock::new(solar_system),
+            snapshot,
+            snapshots,
+            frames: AtomicU64::new(0),
+            commands: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Keep the command of the ship for `apply_commands`
+    pub fn buffer_command(&self, ship_uuid: Uuid, client_time_ms: u64, command: ClientCommand) {
+        self.commands.lock().push(BufferedCommand {
+            ship_uuid,
+            client_time_ms,
+            command,
+        });
+    }
+
+    /// Apply the buffered commands in the order the clients sent them, at the tick boundary,
+    /// so that a burst after a lag spike is not one jump. The refused ones with their reason
+    pub fn apply_commands(&self) -> Vec<(Uuid, &'static str)> {
+        let mut commands = std::mem::take(&mut *self.commands.lock());
+        // Stable, the commands sent at the same time keep the order they arrived in
+        commands.sort_by_key(|buffered| buffered.client_time_ms);
+
+        let solar_system = self.solar_system.read();
+        commands
+            .into_iter()
+            .filter_map(|buffered| {
+                // The ship may have left in the meantime
+                let ship = solar_system.ships.get(&buffered.ship_uuid)?;
+                let result = buffered.command.apply(&mut ship.lock());
+                result.err().map(|reason| (buffered.ship_uuid, reason))
+            })
+            .collect()
+    }
+
+    /// Run `steps` steps of `step` seconds, then publish the snapshot to the clients of the room
+    pub fn advance(&self, steps: u32, step: f64, server_time_ms: u64) -> Arc<Snapshot> {
+        let snapshot = {
+            let mut solar_system = self.solar_system.write();
+            for _ in 0..steps {
+                solar_system.advance(step);
+            }
+            solar_system.snapshot(server_time_ms)
+        };
+        self.publish(snapshot)
+    }
+
+    /// Make `snapshot` the latest one and send it to the clients of the room
+    pub fn publish(&self, mut snapshot: Snapshot) -> Arc<Snapshot> {
+        snapshot.frame = self.frames.fetch_add(1, Ordering::Relaxed);
+        let snapshot = Arc::new(snapshot);
+        *self.snapshot.write() = Arc::clone(&snapshot);
+        // Fails only when nobody is connected
+        let _ = self.snapshots.send(Arc::clone(&snapshot));
+        snapshot
+    }
+
+    pub fn latest(&self) -> Arc<Snapshot> {
+        Arc::clone(&self.snapshot.read())
+    }
+}
+
+/// Every room by name. They are created when a client asks for one, and dropped once
+/// their last ship left, except the default room
+pub struct Rooms {
+    template: RwLock<SolarSystem>, // copied for every new room, without any ship
+    max_players: Option<usize>,    // ships of every room together
+    rooms: RwLock<HashMap<String, Arc<Room>>>,
+    started_at: Instant, // origin of the server time given to the clients
+}
+
+impl Rooms {
+    pub fn new(template: SolarSystem, max_players: Option<usize>) -> Self {
+        let default_room = Arc::new(Room::new(DEFAULT_ROOM, template.clone(), 0));
+        let rooms = HashMap::from([(DEFAULT_ROOM.to_string(), default_room)]);
+
+        Self {
+            template: RwLock::new(template),
+            max_players,
+            rooms: RwLock::new(rooms),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Monotonic, unlike the clock of the system
+    pub fn server_time_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    /// Letters, digits, `-` and `_`, 32 characters at most
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_ROOM_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    pub fn default_room(&self) -> Arc<Room> {
+        Arc::clone(&self.rooms.read()[DEFAULT_ROOM])
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Room>> {
+        self.rooms.read().get(name).cloned()
+    }
+
+    /// Copy of the list, to go through the rooms without holding the lock
+    pub fn all(&self) -> Vec<Arc<Room>> {
+        self.rooms.read().values().cloned().collect()
+    }
+
+    /// Put the ship in the room `name`, a new ship of the room when `ship` is `None`.
+    /// The room is created if needed, under the same lock so it cannot be dropped in between.
+    /// `None` when the server is full, the ship is then left in `ship`
+    pub fn join(&self, name: &str, ship: &mut Option<TheShip>) -> Option<(Arc<Room>, Uuid)> {
+        let mut rooms = self.rooms.write();
+        // Counted under the lock, two connections cannot take the last place
+        if let Some(max_players) = self.max_players {
+            let players: usize = rooms
+                .values()
+                .map(|room| room.solar_system.read().ships.len())
+                .sum();
+            if players >= max_players {
+                return None;
+            }
+        }
+
+        let room = rooms
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                println!("Room {} created", name);
+                Arc::new(Room::new(
+                    name,
+                    self.template.read().clone(),
+                    self.server_time_ms(),
+                ))
+            })
+            .clone();
+
+        let mut solar_system = room.solar_system.write();
+        let ship = ship.take().unwrap_or_else(|| solar_system.new_ship());
+        let uuid = ship.uuid;
+        solar_system.add_ship(Arc::new(Mutex::new(ship)));
+        drop(solar_system);
+
+        Some((room, uuid))
+    }
+
+    /// Add the planet to every room, so that they keep the same planets
+    pub fn add_planet(&self, planet: Planet) -> Result<(), &'static str> {
+        let rooms = self.rooms.write();
+        self.template.write().add_planet(planet.clone())?;
+        for room in rooms.values() {
+            // The rooms have the planets of the template, the name is free in all of them
+            let _ = room.solar_system.write().add_planet(planet.clone());
+        }
+        Ok(())
+    }
+
+    /// Remove the planet or the moon from every room, false when there is none with this name
+    pub fn remove_planet(&self, name: &str) -> bool {
+        let rooms = self.rooms.write();
+        if !self.template.write().remove_planet(name) {
+            return false;
+        }
+        for room in rooms.values() {
+            room.solar_system.write().remove_planet(name);
+        }
+        true
+    }
+
+    /// Drop the rooms left without any ship, except the default one
+    pub fn drop_empty(&self) {
+        self.rooms.write().retain(|name, room| {
+            let keep = name == DEFAULT_ROOM || !room.solar_system.read().ships.is_empty();
+            if !keep {
+                println!("Room {} closed", name);
+            }
+            keep
+        });
+    }
+
+    /// Room where the ship is flying
+    pub fn room_of(&self, uuid: Uuid) -> Option<Arc<Room>> {
+        self.rooms
+            .read()
+            .values()
+            .find(|room| room.solar_system.read().ships.contains_key(&uuid))
+            .cloned()
+    }
+
+    /// Look for the ship in every room
+    pub fn find_ship(&self, uuid: Uuid) -> Option<Arc<Mutex<TheShip>>> {
+        self.rooms
+            .read()
+            .values()
+            .find_map(|room| room.solar_system.read().ships.get(&uuid).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solar_system::DEFAULT_GRAVITATIONAL_CONSTANT;
+
+    #[test]
+    fn rooms_left_empty_are_dropped_except_the_default_one() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        let (alpha, uuid) = rooms.join("alpha", &mut None).unwrap();
+        rooms.join("beta", &mut None).unwrap();
+        assert_eq!(rooms.all().len(), 3);
+        assert!(rooms.find_ship(uuid).is_some());
+
+        alpha.solar_system.write().remove_ship(uuid);
+        rooms.drop_empty();
+        let mut names: Vec<String> = rooms.all().iter().map(|room| room.name.clone()).collect();
+        names.sort();
+        assert_eq!(names, ["beta", DEFAULT_ROOM]);
+        assert!(rooms.find_ship(uuid).is_none());
+    }
+
+    #[test]
+    fn room_names_are_short_and_plain() {
+        assert!(Rooms::is_valid_name("alpha-2_b"));
+        assert!(!Rooms::is_valid_name(""));
+        assert!(!Rooms::is_valid_name("with space"));
+        assert!(!Rooms::is_valid_name(&"a".repeat(MAX_ROOM_NAME_LENGTH + 1)));
+    }
+
+    fn renaming(name: &str) -> ClientCommand {
+        ClientCommand {
+            name: Some(name.to_string()),
+            ..ClientCommand::default()
+        }
+    }
+
+    #[test]
+    fn buffered_commands_are_applied_in_the_order_they_were_sent() {
+        let rooms = Rooms::new(SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT), None);
+        let (room, uuid) = rooms.join(DEFAULT_ROOM, &mut None).unwrap();
+        let name = || room.solar_system.read().ships[&uuid].lock().name.clone();
+        let before = name();
+
+        // The last one sent arrives first
+        room.buffer_command(uuid, 1030, renaming("third"));
+        room.buffer_command(uuid, 1010, renaming("first"));
+        room.buffer_command(uuid, 1020, renaming("second"));
+        assert_eq!(name(), before);
+
+        assert!(room.apply_commands().is_empty());
+        assert_eq!(name(), "third");
+    }
+}
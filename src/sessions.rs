@@ -0,0 +1,38 @@
+use crate::ship::TheShip;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Ships of the clients which disconnected recently, by session token,
+/// so a client coming back in time gets its ship where it left it
+pub struct SessionCache {
+    ttl: Duration,
+    ships: HashMap<Uuid, (TheShip, Instant)>, // with the time of the disconnection
+}
+
+impl SessionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            ships: HashMap::new(),
+        }
+    }
+
+    /// Keep the ship of a closed connection until the session expires
+    pub fn store(&mut self, session: Uuid, ship: TheShip) {
+        self.purge_expired();
+        self.ships.insert(session, (ship, Instant::now()));
+    }
+
+    /// Give back the ship of the session, if it did not expire
+    pub fn take(&mut self, session: Uuid) -> Option<TheShip> {
+        self.purge_expired();
+        self.ships.remove(&session).map(|(ship, _)| ship)
+    }
+
+    fn purge_expired(&mut self) {
+        let ttl = self.ttl;
+        self.ships
+            .retain(|_, (_, disconnected_at)| disconnected_at.elapsed() < ttl);
+    }
+}
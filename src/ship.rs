@@ -1,8 +1,242 @@
+use crate::quaternion::Quaternion;
+use crate::vec3::{self, Vec3};
 use serde::Serialize;
-use serde_json::json;
+use std::collections::VecDeque;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize)]
+/// Softening length of the gravity, keeps the pull finite near the center of a body
+pub const DEFAULT_GRAVITY_SOFTENING: f64 = 5.0;
+/// Within this distance of a body the gravity of a step is split in sub-steps,
+/// `CLOSE_APPROACH_DISTANCE / distance` of them up to `MAX_GRAVITY_SUBSTEPS`
+const CLOSE_APPROACH_DISTANCE: f64 = 40.0;
+const MAX_GRAVITY_SUBSTEPS: u32 = 16;
+pub const DEFAULT_MAX_SPEED: f64 = 40.0;
+/// Where the ships appear and respawn, before `WORLD_SCALE`
+pub const SPAWN_POINT: (f64, f64, f64) = (0.0, 0.0, 450.0);
+/// Fraction of the speed lost per second while no translation engine fires
+const LINEAR_DRAG: f64 = 0.05;
+/// Rate (per second) at which the angular velocity settles to the one asked by the
+/// rotation engines, or decays to zero once they are released
+const ANGULAR_DAMPING: f64 = 4.0;
+/// Below this (radians per second) the ship stops turning
+const MIN_ANGULAR_SPEED: f64 = 1e-4;
+/// Seconds for a translation engine to go from off to full power, and back
+pub const DEFAULT_SPOOL_TIME: f64 = 0.5;
+/// Positions kept in the trail of a ship, one per step
+pub const DEFAULT_TRAIL_LENGTH: usize = 60;
+/// Multiplier of the translation power while boosting, and of the fuel it burns
+const BOOST_FACTOR: f64 = 2.5;
+const BOOST_FUEL_FACTOR: f64 = 3.0;
+/// Seconds of continuous boost before the afterburner overheats, then seconds before using it again
+const BOOST_MAX_DURATION: f64 = 3.0;
+const BOOST_COOLDOWN: f64 = 5.0;
+pub const MAX_FUEL: f64 = 100.0;
+/// Fuel burnt per second by each active engine
+const FUEL_CONSUMPTION: f64 = 1.0;
+/// Fuel recovered per second while refueling near a planet
+const FUEL_REGENERATION: f64 = 5.0;
+pub const MAX_HEALTH: f64 = 100.0;
+/// Impacts slower than this do no damage, each unit of speed above it costs `DAMAGE_PER_SPEED`
+const DAMAGE_SPEED_THRESHOLD: f64 = 8.0;
+const DAMAGE_PER_SPEED: f64 = 5.0;
+/// Acceleration pushing a ship back, per unit of distance beyond the edge of the world
+const BOUNDARY_STIFFNESS: f64 = 0.5;
+/// Seconds a destroyed ship stays a wreck before respawning
+const RESPAWN_DELAY: f64 = 3.0;
+const MAX_NAME_LENGTH: usize = 24;
+/// Range of the power a pilot can give to the translation and the rotation engines
+const MIN_ENGINE_POWER: f64 = 0.1;
+const MAX_ENGINE_POWER: f64 = 5.0;
+const DEFAULT_NAME: &str = "Hearthian";
+
+// Axes of the ship in its own frame, the orientation maps them to the world
+const LOCAL_FORWARD: (f64, f64, f64) = (1.0, 0.0, 0.0);
+const LOCAL_UP: (f64, f64, f64) = (0.0, 1.0, 0.0);
+const LOCAL_LEFT: (f64, f64, f64) = (0.0, 0.0, 1.0);
+
+/// A body attracting the ships (the sun, a planet...) at its current position
+#[derive(Debug, Clone, Copy)]
+pub struct Attractor {
+    pub position: (f64, f64, f64),
+    pub mass: f64,
+    pub softening: f64, // `GRAVITY_SOFTENING`
+}
+
+impl Attractor {
+    /// Acceleration given to anything at `position`, with Plummer softening: the distance
+    /// is taken as `sqrt(r² + softening²)`, so the pull fades instead of blowing up at the
+    /// center of the body
+    pub fn pull(&self, position: (f64, f64, f64), gravitational_constant: f64) -> (f64, f64, f64) {
+        let dx = self.position.0 - position.0;
+        let dy = self.position.1 - position.1;
+        let dz = self.position.2 - position.2;
+
+        let distance_squared = dx.powi(2) + dy.powi(2) + dz.powi(2) + self.softening.powi(2);
+        if distance_squared == 0.0 {
+            // Right at the center without softening, pulled evenly in every direction
+            return (0.0, 0.0, 0.0);
+        }
+        let factor = gravitational_constant * self.mass / distance_squared.powf(1.5);
+        (dx * factor, dy * factor, dz * factor)
+    }
+}
+
+/// Circular orbit inserted around a planet or a moon. The ship is then captured by it: it
+/// follows the body and only feels its gravity, so the orbit stays circular
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Orbit {
+    pub target: String,
+    pub radius: f64,
+    #[serde(skip)]
+    relative_speed: (f64, f64, f64), // speed of the ship in the frame of the body
+}
+
+/// A body a ship can orbit, with its velocity to get the motion of the ship relative to it
+#[derive(Debug, Clone, Copy)]
+pub struct OrbitedBody<'a> {
+    pub name: &'a str,
+    pub attractor: Attractor,
+    pub velocity: (f64, f64, f64),
+}
+
+/// Orbit of a ship around the body pulling it the most, from its position and its speed
+/// relative to that body. The distances are from the center of the body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OrbitalElements {
+    /// Bound to the body
+    Elliptic {
+        body: String,
+        semi_major_axis: f64,
+        eccentricity: f64,
+        periapsis: f64,
+        apoapsis: f64,
+    },
+    /// Fast enough to leave the body for good, `outbound` once past the periapsis
+    Hyperbolic {
+        body: String,
+        eccentricity: f64,
+        periapsis: f64,
+        outbound: bool,
+    },
+}
+
+impl OrbitalElements {
+    /// `mu` is the gravitational constant times the mass of the body.
+    /// `None` at the center of the body, where there is no orbit
+    fn new(body: &str, position: (f64, f64, f64), speed: (f64, f64, f64), mu: f64) -> Option<Self> {
+        let distance = dot(position, position).sqrt();
+        if distance == 0.0 || mu <= 0.0 {
+            return None;
+        }
+        let energy = dot(speed, speed) / 2.0 - mu / distance;
+        let momentum = cross(position, speed);
+        let eccentricity = (1.0 + 2.0 * energy * dot(momentum, momentum) / mu.powi(2))
+            .max(0.0)
+            .sqrt();
+        // Semi-latus rectum, also defined for the parabola
+        let semi_latus_rectum = dot(momentum, momentum) / mu;
+        let periapsis = semi_latus_rectum / (1.0 + eccentricity);
+
+        Some(if energy < 0.0 {
+            OrbitalElements::Elliptic {
+                body: body.to_string(),
+                semi_major_axis: -mu / (2.0 * energy),
+                eccentricity,
+                periapsis,
+                apoapsis: semi_latus_rectum / (1.0 - eccentricity),
+            }
+        } else {
+            OrbitalElements::Hyperbolic {
+                body: body.to_string(),
+                eccentricity,
+                periapsis,
+                outbound: dot(position, speed) > 0.0,
+            }
+        })
+    }
+}
+
+/// Tuning of the new ships, from the configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShipSettings {
+    pub spool_time: f64,
+    pub max_speed: f64,
+    pub trail_length: usize, // no trail when 0
+    pub integrator: Integrator,
+}
+
+impl Default for ShipSettings {
+    fn default() -> Self {
+        Self {
+            spool_time: DEFAULT_SPOOL_TIME,
+            max_speed: DEFAULT_MAX_SPEED,
+            trail_length: DEFAULT_TRAIL_LENGTH,
+            integrator: Integrator::Euler,
+        }
+    }
+}
+
+/// How `TheShip::update` moves the ship along its speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// Semi-implicit Euler: the speed first, then the position with the new speed
+    Euler,
+    /// Kick-drift-kick, the gravity taken half before and half after the move.
+    /// Orbits keep their energy better over long durations
+    Leapfrog,
+}
+
+impl Integrator {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "euler" => Some(Integrator::Euler),
+            "leapfrog" => Some(Integrator::Leapfrog),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a ship flying into the surface of a planet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceCollision {
+    /// The ship stops on the surface, it keeps only its speed along it
+    Stop,
+    /// The ship bounces off the surface
+    Bounce,
+}
+
+impl SurfaceCollision {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "stop" => Some(SurfaceCollision::Stop),
+            "bounce" => Some(SurfaceCollision::Bounce),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a ship leaving the playable sphere around the sun
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldBoundary {
+    /// A force growing with the distance pulls the ship back
+    Repel,
+    /// The ship comes back from the opposite side
+    Wrap,
+}
+
+impl WorldBoundary {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "repel" => Some(WorldBoundary::Repel),
+            "wrap" => Some(WorldBoundary::Wrap),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Engines {
     pub power: f64,
     pub front: bool,
@@ -11,36 +245,168 @@ pub struct Engines {
     pub right: bool,
     pub up: bool,
     pub down: bool,
+    pub boost: bool, // afterburner requested, only applies while a translation engine fires
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl Engines {
+    pub fn active_count(&self) -> usize {
+        [
+            self.front, self.back, self.left, self.right, self.up, self.down,
+        ]
+        .iter()
+        .filter(|&&active| active)
+        .count()
+    }
+
+    pub fn any_active(&self) -> bool {
+        self.active_count() > 0
+    }
+}
+
+/// How far each translation engine is spooled up, from 0 (off) to 1 (full power)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Throttle {
+    pub front: f64,
+    pub back: f64,
+    pub left: f64,
+    pub right: f64,
+    pub up: f64,
+    pub down: f64,
+}
+
+impl Throttle {
+    /// Move each throttle toward 1 while its engine is held, and back toward 0 once released
+    fn spool(&mut self, engines: &Engines, delta_time: f64, spool_time: f64) {
+        let step = if spool_time > 0.0 {
+            delta_time / spool_time
+        } else {
+            1.0
+        };
+        let ramp = |throttle: &mut f64, held: bool| {
+            *throttle = if held {
+                (*throttle + step).min(1.0)
+            } else {
+                (*throttle - step).max(0.0)
+            };
+        };
+
+        ramp(&mut self.front, engines.front);
+        ramp(&mut self.back, engines.back);
+        ramp(&mut self.left, engines.left);
+        ramp(&mut self.right, engines.right);
+        ramp(&mut self.up, engines.up);
+        ramp(&mut self.down, engines.down);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct RotationEngines {
     pub power: f64,
     pub left: bool,
     pub right: bool,
     pub up: bool,
     pub down: bool,
+    pub roll_left: bool,
+    pub roll_right: bool,
+}
+
+impl RotationEngines {
+    pub fn active_count(&self) -> usize {
+        [
+            self.left,
+            self.right,
+            self.up,
+            self.down,
+            self.roll_left,
+            self.roll_right,
+        ]
+        .iter()
+        .filter(|&&active| active)
+        .count()
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TheShip {
     pub uuid: Uuid,
+    pub name: String,
+    #[serde(serialize_with = "vec3::serialize")]
     pub speed: (f64, f64, f64),
+    #[serde(serialize_with = "vec3::serialize")]
     pub position: (f64, f64, f64),
+    #[serde(serialize_with = "vec3::serialize")]
+    pub prev_position: (f64, f64, f64), // at the start of the last step, to interpolate
+    #[serde(serialize_with = "vec3::serialize")]
     pub direction: (f64, f64, f64),
+    #[serde(serialize_with = "vec3::serialize")]
+    pub up: (f64, f64, f64),
+    pub orientation: Quaternion,
     pub engines: Engines,
+    pub throttle: Throttle,
+    pub thruster_output: Throttle, // thrust applied by the last step, 0..1 per direction
+    pub spool_time: f64,
     pub rotation_engines: RotationEngines,
+    pub angular_velocity: (f64, f64, f64), // yaw, pitch and roll, radians per second
     pub angle: f64,
     pub pitch: f64,
+    pub roll: f64,
+    pub max_speed: f64,
+    pub integrator: Integrator,
+    pub fuel: f64,
+    pub boost_heat: f64, // seconds of boost accumulated, cools down when not boosting
+    pub boost_cooldown: f64, // seconds left before the boost can be engaged again
+    pub landed_on: Option<String>,
+    pub autopilot: Option<String>, // planet or moon whose velocity the ship matches
+    pub health: f64,
+    pub respawn_in: Option<f64>, // seconds left before respawning, set while destroyed
+    pub last_seq: u64, // sequence number of the last command applied, 0 before the first one
+    pub probe_requested: bool, // launched by the next step
+    pub orbit: Option<Orbit>, // left as soon as an engine fires
+    pub orbit_request: Option<(String, f64)>, // target and radius, inserted by the next step
+    #[serde(skip)]
+    pub trail: VecDeque<(f64, f64, f64)>, // last positions, the oldest first
+    #[serde(skip)]
+    pub trail_length: usize,
+    #[serde(skip)]
+    pub spawn_point: (f64, f64, f64), // `SPAWN_POINT` in the scale of the world
+    #[serde(skip)]
+    pub escaped: bool, // set by `read_orbit` after each step
+    #[serde(skip)]
+    pub linear_drag: f64, // `LINEAR_DRAG`, 0 for a ship keeping its speed
+    #[serde(skip)]
+    pub orbital_elements: Option<OrbitalElements>,
+}
+
+/// What `TheShip::to_json` sends, the fields of the ship flattened with the others
+#[derive(Serialize)]
+struct ShipState<'a> {
+    #[serde(flatten)]
+    ship: &'a TheShip,
+    trail: Vec<Vec3>,
+    boosting: bool,
+    max_fuel: f64,
+    max_health: f64,
+    destroyed: bool,
+    escaped: bool,
+    orbital_elements: &'a Option<OrbitalElements>,
 }
 
 impl TheShip {
-    pub fn new() -> Self {
-        Self {
-            uuid: Uuid::new_v4(),
+    /// Two ships closer than twice this radius are colliding
+    pub const COLLISION_RADIUS: f64 = 2.0;
+
+    /// A new ship at the spawn point, the uuid comes from `SolarSystem::new_ship`
+    /// so that a seeded world gives the same uuids every time
+    pub fn new_with_uuid(uuid: Uuid) -> Self {
+        let mut ship = Self {
+            uuid,
+            name: DEFAULT_NAME.to_string(),
             speed: (0.0, 0.0, 0.0),
-            position: (0.0, 0.0, 450.0),
+            position: SPAWN_POINT,
+            prev_position: SPAWN_POINT,
             direction: (1.0, 0.0, 0.0), // Always normalized
+            up: (0.0, 1.0, 0.0),        // Always normalized and orthogonal to direction
+            orientation: Quaternion::from_axis_angle(LOCAL_UP, std::f64::consts::FRAC_PI_2),
             engines: Engines {
                 power: 1.0,
                 front: false,
@@ -49,110 +415,711 @@ impl TheShip {
                 right: false,
                 up: false,
                 down: false,
+                boost: false,
             },
+            throttle: Throttle::default(),
+            thruster_output: Throttle::default(),
+            spool_time: DEFAULT_SPOOL_TIME,
             rotation_engines: RotationEngines {
                 power: 0.5,
                 left: false,
                 right: false,
                 up: false,
                 down: false,
+                roll_left: false,
+                roll_right: false,
             },
+            angular_velocity: (0.0, 0.0, 0.0),
             angle: -std::f64::consts::FRAC_PI_2,
             pitch: 0.0,
-        }
+            roll: 0.0,
+            max_speed: DEFAULT_MAX_SPEED,
+            integrator: Integrator::Euler,
+            fuel: MAX_FUEL,
+            boost_heat: 0.0,
+            boost_cooldown: 0.0,
+            landed_on: None,
+            autopilot: None,
+            health: MAX_HEALTH,
+            respawn_in: None,
+            last_seq: 0,
+            probe_requested: false,
+            orbit: None,
+            orbit_request: None,
+            trail: VecDeque::new(),
+            trail_length: DEFAULT_TRAIL_LENGTH,
+            spawn_point: SPAWN_POINT,
+            escaped: false,
+            linear_drag: LINEAR_DRAG,
+            orbital_elements: None,
+        };
+        ship.update_axes();
+        ship
     }
 
+    /// Full state of the ship, for its own client: every serialized field, with the trail
+    /// and the values derived from the fields
     pub fn to_json(&self) -> serde_json::Value {
-        json!({
-            "uuid": self.uuid.to_string(),
-            "speed": self.speed,
-            "position": self.position,
-            "direction": self.direction,
-            "engines": {
-                "power": self.engines.power,
-                "front": self.engines.front,
-                "back": self.engines.back,
-                "left": self.engines.left,
-                "right": self.engines.right,
-                "up": self.engines.up,
-                "down": self.engines.down,
-            },
-            "rotation_engines": {
-                "power": self.rotation_engines.power,
-                "left": self.rotation_engines.left,
-                "right": self.rotation_engines.right,
-                "up": self.rotation_engines.up,
-                "down": self.rotation_engines.down,
-            },
-        })
+        let state = ShipState {
+            ship: self,
+            trail: self.trail.iter().copied().map(Vec3::from).collect(),
+            boosting: self.is_boosting(),
+            max_fuel: MAX_FUEL,
+            max_health: MAX_HEALTH,
+            destroyed: self.is_destroyed(),
+            escaped: self.escaped,
+            orbital_elements: &self.orbital_elements,
+        };
+        serde_json::to_value(state).unwrap()
     }
 
-    pub fn update(&mut self, delta_time: f64) {
+    pub fn update(
+        &mut self,
+        delta_time: f64,
+        attractors: &[Attractor],
+        gravitational_constant: f64,
+    ) {
         // Update the direction
         self.rotate(delta_time);
-
-        // Update the speed
+        // Update the speed, the engines fire once per step
         self.accelerate(delta_time);
 
-        // Update the position
+        let substeps = self.gravity_substeps(delta_time, attractors);
+        self.integrate(delta_time, substeps, attractors, gravitational_constant);
+
+        // Off the hot path unless built with `--features trace-ticks`
+        #[cfg(feature = "trace-ticks")]
+        eprintln!(
+            "Ship {} speed {:?} position {:?}",
+            self.uuid, self.speed, self.position
+        );
+    }
+
+    /// Sub-steps of the gravity for this step, more the closer the ship can fly to a body
+    /// during it, where the pull changes too fast along one long step
+    fn gravity_substeps(&self, delta_time: f64, attractors: &[Attractor]) -> u32 {
+        let reach = dot(self.speed, self.speed).sqrt() * delta_time;
+        let closest = attractors
+            .iter()
+            .filter(|attractor| attractor.mass > 0.0)
+            .map(|attractor| self.distance_to(attractor.position) - reach)
+            .fold(f64::INFINITY, f64::min);
+        if closest >= CLOSE_APPROACH_DISTANCE {
+            return 1;
+        }
+        // Also when the step can reach the body itself
+        let closest = closest.max(CLOSE_APPROACH_DISTANCE / MAX_GRAVITY_SUBSTEPS as f64);
+        ((CLOSE_APPROACH_DISTANCE / closest).ceil() as u32).min(MAX_GRAVITY_SUBSTEPS)
+    }
+
+    /// Gravity, drag and motion over `substeps` equal parts of the step.
+    /// Split steps are leapfrog ones whatever the integrator: the speed of the Euler steps
+    /// lags half a step behind, which injects energy whenever the length of the steps changes
+    fn integrate(
+        &mut self,
+        delta_time: f64,
+        substeps: u32,
+        attractors: &[Attractor],
+        gravitational_constant: f64,
+    ) {
+        let integrator = if substeps > 1 {
+            Integrator::Leapfrog
+        } else {
+            self.integrator
+        };
+        let substep = delta_time / substeps as f64;
+        for _ in 0..substeps {
+            match integrator {
+                Integrator::Euler => {
+                    self.apply_gravity(substep, attractors, gravitational_constant);
+                    self.apply_drag(substep);
+                    self.clamp_speed();
+
+                    // Update the position
+                    self.drift(substep);
+                }
+                Integrator::Leapfrog => {
+                    self.apply_gravity(substep / 2.0, attractors, gravitational_constant);
+                    self.apply_drag(substep);
+                    self.clamp_speed();
+
+                    self.drift(substep);
+                    // The pull at the new position
+                    self.apply_gravity(substep / 2.0, attractors, gravitational_constant);
+                }
+            }
+        }
+    }
+
+    fn drift(&mut self, delta_time: f64) {
         self.position.0 += self.speed.0 * delta_time;
         self.position.1 += self.speed.1 * delta_time;
         self.position.2 += self.speed.2 * delta_time;
     }
 
+    /// Thrust of each engine, scaled by how far it is spooled up and by the boost
     pub fn accelerate(&mut self, delta_time: f64) {
-        if self.engines.front {
-            self.speed.0 -= self.direction.0 * delta_time * self.engines.power;
-            self.speed.1 -= self.direction.1 * delta_time * self.engines.power;
-            self.speed.2 -= self.direction.2 * delta_time * self.engines.power;
+        let boosting = self.is_boosting();
+        self.update_boost(boosting, delta_time);
+
+        let fuel_factor = if boosting { BOOST_FUEL_FACTOR } else { 1.0 };
+        let engine_load = self.engines.active_count() as f64 * fuel_factor;
+        if !self.burn_fuel(engine_load, delta_time) {
+            // Empty tank, the engines die at once
+            self.throttle = Throttle::default();
+            self.thruster_output = Throttle::default();
+            return;
         }
+        self.throttle
+            .spool(&self.engines, delta_time, self.spool_time);
+        // The boost multiplies the thrust but is shown apart, by `boosting`
+        self.thruster_output = self.throttle.clone();
+
+        let boost = if boosting { BOOST_FACTOR } else { 1.0 };
+        let power = self.engines.power * boost * delta_time;
+        let throttle = &self.throttle;
 
-        if self.engines.back {
-            self.speed.0 += self.direction.0 * delta_time * self.engines.power;
-            self.speed.1 += self.direction.1 * delta_time * self.engines.power;
-            self.speed.2 += self.direction.2 * delta_time * self.engines.power;
+        // Front and back along the nose
+        let forward = throttle.back - throttle.front;
+        self.speed.0 += self.direction.0 * forward * power;
+        self.speed.1 += self.direction.1 * forward * power;
+        self.speed.2 += self.direction.2 * forward * power;
+
+        // Vertical local direction, follows the roll of the ship
+        let vertical_local = self.up;
+
+        // Up and down vertical acceleration
+        let vertical = throttle.down - throttle.up;
+        self.speed.0 += vertical_local.0 * vertical * power;
+        self.speed.1 += vertical_local.1 * vertical * power;
+        self.speed.2 += vertical_local.2 * vertical * power;
+
+        // Lateral local direction, perpendicular to both the nose and the local vertical
+        let lateral_local = normalized_cross(self.direction, vertical_local);
+
+        // Left and right lateral acceleration
+        let lateral = throttle.left - throttle.right;
+        self.speed.0 += lateral_local.0 * lateral * power;
+        self.speed.1 += lateral_local.1 * lateral * power;
+        self.speed.2 += lateral_local.2 * lateral * power;
+    }
+
+    /// True when the afterburner multiplies the thrust this tick
+    pub fn is_boosting(&self) -> bool {
+        self.engines.boost && self.boost_cooldown <= 0.0 && self.is_thrusting()
+    }
+
+    /// Heat up the afterburner while boosting, it overheats into a cooldown after too long
+    fn update_boost(&mut self, boosting: bool, delta_time: f64) {
+        self.boost_cooldown = (self.boost_cooldown - delta_time).max(0.0);
+
+        if boosting {
+            self.boost_heat += delta_time;
+            if self.boost_heat >= BOOST_MAX_DURATION {
+                self.boost_heat = 0.0;
+                self.boost_cooldown = BOOST_COOLDOWN;
+            }
+        } else {
+            self.boost_heat = (self.boost_heat - delta_time).max(0.0);
         }
+    }
 
-        // Vertical local direction
-        let vertical_local = (
-            -self.direction.0 * self.pitch.sin(),
-            self.pitch.cos(),
-            -self.direction.2 * self.pitch.sin(),
+    /// Pull the ship toward each attractor (inverse-square law)
+    /// The distance is softened so the force stays bounded when passing through a body
+    pub fn apply_gravity(
+        &mut self,
+        delta_time: f64,
+        attractors: &[Attractor],
+        gravitational_constant: f64,
+    ) {
+        for attractor in attractors {
+            let pull = attractor.pull(self.position, gravitational_constant);
+            self.speed.0 += pull.0 * delta_time;
+            self.speed.1 += pull.1 * delta_time;
+            self.speed.2 += pull.2 * delta_time;
+        }
+    }
+
+    /// Slowly damp the speed when the pilot lets go of the engines
+    pub fn apply_drag(&mut self, delta_time: f64) {
+        if self.is_thrusting() {
+            return;
+        }
+
+        let factor = (1.0 - self.linear_drag * delta_time).max(0.0);
+        self.speed.0 *= factor;
+        self.speed.1 *= factor;
+        self.speed.2 *= factor;
+    }
+
+    /// Limit the magnitude of the speed to `max_speed`, keeping its direction
+    pub fn clamp_speed(&mut self) {
+        let norm = (self.speed.0.powi(2) + self.speed.1.powi(2) + self.speed.2.powi(2)).sqrt();
+        if norm > self.max_speed {
+            let factor = self.max_speed / norm;
+            self.speed.0 *= factor;
+            self.speed.1 *= factor;
+            self.speed.2 *= factor;
+        }
+    }
+
+    /// True when a translation engine is firing (the tank is not empty)
+    pub fn is_thrusting(&self) -> bool {
+        self.engines.any_active() && self.fuel > 0.0
+    }
+
+    /// Engage the autopilot, the manual translation engines are released
+    pub fn engage_autopilot(&mut self, target: &str) {
+        self.autopilot = Some(target.to_string());
+        self.orbit = None;
+        self.release_engines();
+    }
+
+    /// Put the ship `radius` away from the body, on the side where it already is, with the
+    /// speed of a circular orbit in the plane of the planets, on top of the speed of the body
+    pub fn insert_into_orbit(
+        &mut self,
+        target: &str,
+        radius: f64,
+        body: Attractor,
+        body_velocity: (f64, f64, f64),
+        gravitational_constant: f64,
+    ) {
+        let offset = (
+            self.position.0 - body.position.0,
+            self.position.1 - body.position.1,
+            self.position.2 - body.position.2,
+        );
+        let distance = self.distance_to(body.position);
+        let outward = if distance > f64::EPSILON {
+            (
+                offset.0 / distance,
+                offset.1 / distance,
+                offset.2 / distance,
+            )
+        } else {
+            (1.0, 0.0, 0.0)
+        };
+        // Prograde, as the planets turn, unless the ship is right above the pole
+        let mut prograde = normalized_cross((0.0, 0.0, 1.0), outward);
+        if prograde == (0.0, 0.0, 0.0) {
+            prograde = normalized_cross((0.0, 1.0, 0.0), outward);
+        }
+
+        self.position = (
+            body.position.0 + outward.0 * radius,
+            body.position.1 + outward.1 * radius,
+            body.position.2 + outward.2 * radius,
+        );
+        // Centripetal acceleration v² / r given by the (softened) gravity of the body
+        let pull = body.pull(self.position, gravitational_constant);
+        let orbital_speed = (dot(pull, pull).sqrt() * radius).sqrt();
+        let relative_speed = (
+            prograde.0 * orbital_speed,
+            prograde.1 * orbital_speed,
+            prograde.2 * orbital_speed,
         );
 
-        // Up vertical acceleration
-        if self.engines.up {
-            self.speed.0 -= vertical_local.0 * delta_time * self.engines.power;
-            self.speed.1 -= vertical_local.1 * delta_time * self.engines.power;
-            self.speed.2 -= vertical_local.2 * delta_time * self.engines.power;
+        self.speed = (
+            body_velocity.0 + relative_speed.0,
+            body_velocity.1 + relative_speed.1,
+            body_velocity.2 + relative_speed.2,
+        );
+        self.release_engines();
+        self.throttle = Throttle::default();
+        self.autopilot = None;
+        self.landed_on = None;
+        self.orbit = Some(Orbit {
+            target: target.to_string(),
+            radius,
+            relative_speed,
+        });
+    }
+
+    /// Update of a ship in orbit: it moves with the body by `body_displacement`, and around
+    /// it under its gravity alone
+    pub fn coast_in_orbit(
+        &mut self,
+        delta_time: f64,
+        body: Attractor,
+        body_displacement: (f64, f64, f64),
+        body_velocity: (f64, f64, f64),
+        gravitational_constant: f64,
+    ) {
+        let Some(orbit) = &mut self.orbit else {
+            return;
+        };
+        self.position.0 += body_displacement.0;
+        self.position.1 += body_displacement.1;
+        self.position.2 += body_displacement.2;
+
+        // Semi-implicit Euler, the energy of the orbit does not drift
+        let pull = body.pull(self.position, gravitational_constant);
+        orbit.relative_speed.0 += pull.0 * delta_time;
+        orbit.relative_speed.1 += pull.1 * delta_time;
+        orbit.relative_speed.2 += pull.2 * delta_time;
+        let relative_speed = orbit.relative_speed;
+
+        self.position.0 += relative_speed.0 * delta_time;
+        self.position.1 += relative_speed.1 * delta_time;
+        self.position.2 += relative_speed.2 * delta_time;
+        self.speed = (
+            body_velocity.0 + relative_speed.0,
+            body_velocity.1 + relative_speed.1,
+            body_velocity.2 + relative_speed.2,
+        );
+
+        self.rotate(delta_time);
+        self.thruster_output = Throttle::default();
+    }
+
+    fn release_engines(&mut self) {
+        self.engines.front = false;
+        self.engines.back = false;
+        self.engines.left = false;
+        self.engines.right = false;
+        self.engines.up = false;
+        self.engines.down = false;
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.respawn_in.is_some()
+    }
+
+    /// Damage of a collision at `impact_speed`, the ship is destroyed when its health runs out
+    pub fn take_impact(&mut self, impact_speed: f64) {
+        if self.is_destroyed() || impact_speed <= DAMAGE_SPEED_THRESHOLD {
+            return;
+        }
+
+        self.health -= (impact_speed - DAMAGE_SPEED_THRESHOLD) * DAMAGE_PER_SPEED;
+        if self.health <= 0.0 {
+            self.health = 0.0;
+            self.respawn_in = Some(RESPAWN_DELAY);
+            self.speed = (0.0, 0.0, 0.0);
+            self.throttle = Throttle::default();
+            self.thruster_output = Throttle::default();
+            self.autopilot = None;
+            self.orbit = None;
         }
+    }
+
+    /// Update of a destroyed ship: the wreck stays where it is until it respawns, repaired
+    pub fn wreck(&mut self, delta_time: f64) {
+        let Some(respawn_in) = self.respawn_in else {
+            return;
+        };
+
+        if respawn_in > delta_time {
+            self.respawn_in = Some(respawn_in - delta_time);
+        } else {
+            self.respawn();
+            self.respawn_in = None;
+            self.health = MAX_HEALTH;
+            self.fuel = MAX_FUEL;
+        }
+    }
+
+    /// Back to the spawn point, at rest, keeping the uuid, the name and the controls
+    pub fn respawn(&mut self) {
+        let spawn = TheShip::new_with_uuid(self.uuid);
+        self.position = self.spawn_point;
+        // Not a move, nothing to interpolate
+        self.prev_position = self.spawn_point;
+        self.speed = spawn.speed;
+        self.direction = spawn.direction;
+        self.up = spawn.up;
+        self.orientation = spawn.orientation;
+        self.angular_velocity = spawn.angular_velocity;
+        self.angle = spawn.angle;
+        self.pitch = spawn.pitch;
+        self.roll = spawn.roll;
+        self.throttle = spawn.throttle;
+        self.thruster_output = spawn.thruster_output;
+        self.landed_on = None;
+        self.autopilot = None;
+        self.orbit = None;
+        self.trail.clear();
+    }
+
+    /// Add the position to the trail, the oldest ones beyond `trail_length` are dropped
+    pub fn record_trail(&mut self) {
+        while !self.trail.is_empty() && self.trail.len() >= self.trail_length {
+            self.trail.pop_front();
+        }
+        if self.trail_length > 0 {
+            self.trail.push_back(self.position);
+        }
+    }
+
+    /// Flag the ship as `escaped` while it moves away from the body pulling it the most,
+    /// faster than the escape velocity of that body at its distance, and compute its orbit
+    /// around that body. The flag clears once the ship slows down or turns back
+    pub fn read_orbit(&mut self, bodies: &[OrbitedBody], gravitational_constant: f64) {
+        let pull = |body: &OrbitedBody| {
+            let pull = body.attractor.pull(self.position, gravitational_constant);
+            dot(pull, pull)
+        };
+        let dominant = bodies
+            .iter()
+            .filter(|body| body.attractor.mass > 0.0)
+            .max_by(|a, b| pull(a).total_cmp(&pull(b)));
+
+        self.orbital_elements = dominant.and_then(|body| {
+            let position = (
+                self.position.0 - body.attractor.position.0,
+                self.position.1 - body.attractor.position.1,
+                self.position.2 - body.attractor.position.2,
+            );
+            let speed = (
+                self.speed.0 - body.velocity.0,
+                self.speed.1 - body.velocity.1,
+                self.speed.2 - body.velocity.2,
+            );
+            OrbitalElements::new(
+                body.name,
+                position,
+                speed,
+                gravitational_constant * body.attractor.mass,
+            )
+        });
+        self.escaped = match &self.orbital_elements {
+            Some(OrbitalElements::Hyperbolic { outbound, .. }) => *outbound,
+            _ => false,
+        };
+    }
+
+    /// Let go of every control, for a ship whose pilot is gone
+    pub fn release_controls(&mut self) {
+        self.release_engines();
+        self.engines.boost = false;
+        self.rotation_engines.left = false;
+        self.rotation_engines.right = false;
+        self.rotation_engines.up = false;
+        self.rotation_engines.down = false;
+        self.rotation_engines.roll_left = false;
+        self.rotation_engines.roll_right = false;
+    }
+
+    /// Autopilot thrust: cancel the velocity relative to a moving planet, as fast as the engines allow
+    pub fn match_velocity(&mut self, target_velocity: (f64, f64, f64), delta_time: f64) {
+        let difference = (
+            target_velocity.0 - self.speed.0,
+            target_velocity.1 - self.speed.1,
+            target_velocity.2 - self.speed.2,
+        );
+        let norm = dot(difference, difference).sqrt();
+        if norm < f64::EPSILON || !self.burn_fuel(1.0, delta_time) {
+            return;
+        }
+
+        let factor = (self.engines.power * delta_time / norm).min(1.0);
+        self.speed.0 += difference.0 * factor;
+        self.speed.1 += difference.1 * factor;
+        self.speed.2 += difference.2 * factor;
+    }
+
+    /// Rest on a planet, the ship then follows it instead of being simulated
+    pub fn land_on(&mut self, planet: &str) {
+        self.landed_on = Some(planet.to_string());
+        self.speed = (0.0, 0.0, 0.0);
+    }
+
+    /// Leave the planet with its current velocity
+    pub fn take_off(&mut self, planet_velocity: (f64, f64, f64)) {
+        self.landed_on = None;
+        self.speed = planet_velocity;
+    }
+
+    /// Update of a landed ship: it can still turn, but moves along with its planet
+    pub fn ride(&mut self, delta_time: f64, planet_displacement: (f64, f64, f64)) {
+        self.rotate(delta_time);
+        self.thruster_output = Throttle::default();
+
+        self.position.0 += planet_displacement.0;
+        self.position.1 += planet_displacement.1;
+        self.position.2 += planet_displacement.2;
+    }
+
+    pub fn speed_relative_to(&self, velocity: (f64, f64, f64)) -> f64 {
+        ((self.speed.0 - velocity.0).powi(2)
+            + (self.speed.1 - velocity.1).powi(2)
+            + (self.speed.2 - velocity.2).powi(2))
+        .sqrt()
+    }
+
+    /// Consume the fuel of `active_engines` engines during `delta_time`, boosted ones count more
+    /// Returns false when the tank is empty, the engine inputs are then ignored
+    fn burn_fuel(&mut self, active_engines: f64, delta_time: f64) -> bool {
+        if self.fuel <= 0.0 {
+            return false;
+        }
+
+        self.fuel = (self.fuel - active_engines * FUEL_CONSUMPTION * delta_time).max(0.0);
+        true
+    }
+
+    /// Passive refueling, used while the ship stays close to a planet
+    pub fn refuel(&mut self, delta_time: f64) {
+        self.fuel = (self.fuel + FUEL_REGENERATION * delta_time).min(MAX_FUEL);
+    }
+
+    pub fn distance_to(&self, point: (f64, f64, f64)) -> f64 {
+        ((self.position.0 - point.0).powi(2)
+            + (self.position.1 - point.1).powi(2)
+            + (self.position.2 - point.2).powi(2))
+        .sqrt()
+    }
+
+    /// Whether the state shown to the clients changed by more than `epsilon` since `previous`
+    pub fn differs_from(&self, previous: &TheShip, epsilon: f64) -> bool {
+        let moved = |a: (f64, f64, f64), b: (f64, f64, f64)| {
+            (a.0 - b.0).abs() > epsilon
+                || (a.1 - b.1).abs() > epsilon
+                || (a.2 - b.2).abs() > epsilon
+        };
+
+        moved(self.position, previous.position)
+            || moved(self.speed, previous.speed)
+            || moved(self.direction, previous.direction)
+            || moved(self.up, previous.up)
+            || moved(self.angular_velocity, previous.angular_velocity)
+            || (self.fuel - previous.fuel).abs() > epsilon
+            || (self.boost_cooldown - previous.boost_cooldown).abs() > epsilon
+            || self.name != previous.name
+            || self.engines != previous.engines
+            || self.throttle != previous.throttle
+            || self.thruster_output != previous.thruster_output
+            || self.rotation_engines != previous.rotation_engines
+            || self.landed_on != previous.landed_on
+            || self.autopilot != previous.autopilot
+            || (self.health - previous.health).abs() > epsilon
+            || self.respawn_in.is_some() != previous.respawn_in.is_some()
+    }
+
+    /// Elastic bounce between two ships of the same mass
+    /// The velocities are exchanged along the line joining the ships and the overlap removed
+    pub fn collide_with(&mut self, other: &mut TheShip) {
+        let distance = self.distance_to(other.position);
+        if distance >= 2.0 * Self::COLLISION_RADIUS
+            || distance <= f64::EPSILON
+            || self.is_destroyed()
+            || other.is_destroyed()
+        {
+            return;
+        }
+
+        let normal = (
+            (other.position.0 - self.position.0) / distance,
+            (other.position.1 - self.position.1) / distance,
+            (other.position.2 - self.position.2) / distance,
+        );
+
+        // Knocked out of their orbits
+        self.orbit = None;
+        other.orbit = None;
+
+        // Push the ships apart so they no longer overlap
+        let correction = (2.0 * Self::COLLISION_RADIUS - distance) / 2.0;
+        self.position.0 -= normal.0 * correction;
+        self.position.1 -= normal.1 * correction;
+        self.position.2 -= normal.2 * correction;
+        other.position.0 += normal.0 * correction;
+        other.position.1 += normal.1 * correction;
+        other.position.2 += normal.2 * correction;
+
+        let relative_speed = (
+            other.speed.0 - self.speed.0,
+            other.speed.1 - self.speed.1,
+            other.speed.2 - self.speed.2,
+        );
+        let approach_speed = dot(relative_speed, normal);
+        if approach_speed >= 0.0 {
+            // Already moving away from each other
+            return;
+        }
+
+        self.speed.0 += normal.0 * approach_speed;
+        self.speed.1 += normal.1 * approach_speed;
+        self.speed.2 += normal.2 * approach_speed;
+        other.speed.0 -= normal.0 * approach_speed;
+        other.speed.1 -= normal.1 * approach_speed;
+        other.speed.2 -= normal.2 * approach_speed;
+
+        self.take_impact(-approach_speed);
+        other.take_impact(-approach_speed);
+    }
 
-        // Down vertical acceleration
-        if self.engines.down {
-            self.speed.0 += vertical_local.0 * delta_time * self.engines.power;
-            self.speed.1 += vertical_local.1 * delta_time * self.engines.power;
-            self.speed.2 += vertical_local.2 * delta_time * self.engines.power;
+    /// Push the ship back to the surface of a body it went into, the part of its
+    /// velocity relative to the body which goes into the surface is removed or reflected
+    pub fn collide_with_surface(
+        &mut self,
+        center: (f64, f64, f64),
+        radius: f64,
+        body_velocity: (f64, f64, f64),
+        response: SurfaceCollision,
+    ) {
+        let distance = self.distance_to(center);
+        if distance >= radius || distance <= f64::EPSILON {
+            return;
         }
 
-        // Lateral local direction
-        let lateral_local = (
-            self.direction.1 * 0.0 - self.direction.2 * 1.0,
-            self.direction.2 * 0.0 - self.direction.0 * 0.0,
-            self.direction.0 * 1.0 - self.direction.1 * 0.0,
+        let normal = (
+            (self.position.0 - center.0) / distance,
+            (self.position.1 - center.1) / distance,
+            (self.position.2 - center.2) / distance,
+        );
+        self.position = (
+            center.0 + normal.0 * radius,
+            center.1 + normal.1 * radius,
+            center.2 + normal.2 * radius,
+        );
+
+        let relative_speed = (
+            self.speed.0 - body_velocity.0,
+            self.speed.1 - body_velocity.1,
+            self.speed.2 - body_velocity.2,
         );
+        let inward_speed = dot(relative_speed, normal);
+        if inward_speed >= 0.0 {
+            // Already leaving the surface
+            return;
+        }
+
+        let factor = match response {
+            SurfaceCollision::Stop => 1.0,
+            SurfaceCollision::Bounce => 2.0,
+        };
+        self.speed.0 -= normal.0 * inward_speed * factor;
+        self.speed.1 -= normal.1 * inward_speed * factor;
+        self.speed.2 -= normal.2 * inward_speed * factor;
 
-        // Left lateral acceleration
-        if self.engines.left {
-            self.speed.0 += lateral_local.0 * delta_time * self.engines.power;
-            self.speed.1 += lateral_local.1 * delta_time * self.engines.power;
-            self.speed.2 += lateral_local.2 * delta_time * self.engines.power;
+        self.take_impact(-inward_speed);
+    }
+
+    /// Bring back a ship further than `radius` from the sun
+    pub fn keep_within(&mut self, radius: f64, boundary: WorldBoundary, delta_time: f64) {
+        let distance = self.distance_to((0.0, 0.0, 0.0));
+        if distance <= radius {
+            return;
         }
 
-        // Right lateral acceleration
-        if self.engines.right {
-            self.speed.0 -= lateral_local.0 * delta_time * self.engines.power;
-            self.speed.1 -= lateral_local.1 * delta_time * self.engines.power;
-            self.speed.2 -= lateral_local.2 * delta_time * self.engines.power;
+        let normal = (
+            self.position.0 / distance,
+            self.position.1 / distance,
+            self.position.2 / distance,
+        );
+        match boundary {
+            WorldBoundary::Repel => {
+                let pull = (distance - radius) * BOUNDARY_STIFFNESS * delta_time;
+                self.speed.0 -= normal.0 * pull;
+                self.speed.1 -= normal.1 * pull;
+                self.speed.2 -= normal.2 * pull;
+            }
+            WorldBoundary::Wrap => {
+                // On the edge, the velocity going outward now goes inward
+                self.position = (-normal.0 * radius, -normal.1 * radius, -normal.2 * radius);
+            }
         }
     }
 
@@ -160,34 +1127,911 @@ impl TheShip {
     /// The ship is always normalized
     /// Values between -1.0 and 1.0
     pub fn rotate(&mut self, delta_time: f64) {
-        let rotation_speed = delta_time * self.rotation_engines.power;
+        // Angular velocity asked by the rotation engines, a held engine turns the ship at `power`
+        let rotation_speed = self.rotation_engines.power;
+        let mut target = (0.0, 0.0, 0.0);
+
+        if self.burn_fuel(self.rotation_engines.active_count() as f64, delta_time) {
+            if self.rotation_engines.left {
+                target.0 += rotation_speed;
+            }
+
+            if self.rotation_engines.right {
+                target.0 -= rotation_speed;
+            }
+
+            if self.rotation_engines.up {
+                target.1 -= rotation_speed;
+            }
 
-        if self.rotation_engines.left {
-            self.angle += rotation_speed;
+            if self.rotation_engines.down {
+                target.1 += rotation_speed;
+            }
+
+            if self.rotation_engines.roll_left {
+                target.2 += rotation_speed;
+            }
+
+            if self.rotation_engines.roll_right {
+                target.2 -= rotation_speed;
+            }
+        }
+
+        // Exact solution of `dw/dt = ANGULAR_DAMPING * (target - w)` over the tick
+        let decay = (-ANGULAR_DAMPING * delta_time).exp();
+        let settle = |velocity: f64, target: f64| {
+            let velocity = target + (velocity - target) * decay;
+            if velocity.abs() < MIN_ANGULAR_SPEED {
+                0.0
+            } else {
+                velocity
+            }
+        };
+        self.angular_velocity = (
+            settle(self.angular_velocity.0, target.0),
+            settle(self.angular_velocity.1, target.1),
+            settle(self.angular_velocity.2, target.2),
+        );
+
+        let (yaw, pitch, roll) = self.angular_velocity;
+        if (yaw, pitch, roll) != (0.0, 0.0, 0.0) {
+            // Incremental rotations around the current axes of the ship, no gimbal lock
+            self.orientation = (self.orientation
+                * Quaternion::from_axis_angle(LOCAL_UP, -yaw * delta_time)
+                * Quaternion::from_axis_angle(LOCAL_LEFT, pitch * delta_time)
+                * Quaternion::from_axis_angle(LOCAL_FORWARD, roll * delta_time))
+            .normalized();
         }
 
-        if self.rotation_engines.right {
-            self.angle -= rotation_speed;
+        self.update_axes();
+    }
+
+    /// Derive the direction, the up vector and the Euler angles from the orientation
+    /// The angles are only kept for the clients, the physics relies on the quaternion
+    fn update_axes(&mut self) {
+        // Never let a degenerate orientation give a NaN direction
+        self.orientation = self.orientation.normalized();
+        self.direction = self.orientation.rotate_vector(LOCAL_FORWARD);
+        self.up = self.orientation.rotate_vector(LOCAL_UP);
+
+        self.pitch = self.direction.1.clamp(-1.0, 1.0).asin();
+        self.angle = self.direction.2.atan2(self.direction.0);
+
+        // Roll is measured from the up vector the ship would have without banking
+        let level_up = (
+            -self.angle.cos() * self.pitch.sin(),
+            self.pitch.cos(),
+            -self.angle.sin() * self.pitch.sin(),
+        );
+        let level_left = normalized_cross(self.direction, level_up);
+        self.roll = dot(self.up, level_left).atan2(dot(self.up, level_up));
+    }
+}
+
+/// The display name without its control characters
+/// Returns the reason of the refusal when the name is empty or too long
+pub fn checked_name(name: &str) -> Result<String, &'static str> {
+    let sanitized: String = name.chars().filter(|c| !c.is_control()).collect();
+    let sanitized = sanitized.trim();
+
+    if sanitized.is_empty() {
+        return Err("empty_name");
+    }
+    if sanitized.chars().count() > MAX_NAME_LENGTH {
+        return Err("name_too_long");
+    }
+    Ok(sanitized.to_string())
+}
+
+/// Refuses a power out of `MIN_ENGINE_POWER..=MAX_ENGINE_POWER` (NaN included)
+pub fn checked_power(power: f64) -> Result<f64, &'static str> {
+    if (MIN_ENGINE_POWER..=MAX_ENGINE_POWER).contains(&power) {
+        Ok(power)
+    } else {
+        Err("invalid_power")
+    }
+}
+
+fn dot(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+/// Cross product `a x b`
+fn cross(a: (f64, f64, f64), b: (f64, f64, f64)) -> (f64, f64, f64) {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+/// Cross product `a x b` scaled to unit length, zero when `a` and `b` are parallel
+fn normalized_cross(a: (f64, f64, f64), b: (f64, f64, f64)) -> (f64, f64, f64) {
+    let cross = cross(a, b);
+    let norm = (cross.0.powi(2) + cross.1.powi(2) + cross.2.powi(2)).sqrt();
+    if norm > f64::EPSILON {
+        (cross.0 / norm, cross.1 / norm, cross.2 / norm)
+    } else {
+        (0.0, 0.0, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DELTA_TIME: f64 = 1.0 / 30.0;
+    const EPSILON: f64 = 1e-9;
+
+    #[test]
+    fn stationary_ship_falls_towards_earth() {
+        let earth = Attractor {
+            position: (90.0, 0.0, 0.0),
+            mass: 200.0,
+            softening: DEFAULT_GRAVITY_SOFTENING,
+        };
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.position = (110.0, 0.0, 0.0);
+        ship.update(1.0 / 30.0, &[earth], 1.0);
+
+        assert!(ship.speed.0 < 0.0, "speed {:?}", ship.speed);
+        assert!(ship.speed.1.abs() < 1e-12 && ship.speed.2.abs() < 1e-12);
+        assert!(ship.position.0 < 110.0);
+    }
+
+    #[test]
+    fn gravity_stays_finite_at_the_center_of_a_body() {
+        let earth = Attractor {
+            position: (90.0, 0.0, 0.0),
+            mass: 200.0,
+            softening: DEFAULT_GRAVITY_SOFTENING,
+        };
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.position = earth.position;
+        ship.apply_gravity(1.0 / 30.0, &[earth], 1.0);
+
+        assert_eq!(ship.speed, (0.0, 0.0, 0.0));
+    }
+
+    fn norm(a: (f64, f64, f64)) -> f64 {
+        (a.0.powi(2) + a.1.powi(2) + a.2.powi(2)).sqrt()
+    }
+
+    #[test]
+    fn front_engine_never_exceeds_the_max_speed() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.engines.front = true;
+        for _ in 0..100 * 30 {
+            ship.update(1.0 / 30.0, &[], 1.0);
+            assert!(norm(ship.speed) <= ship.max_speed + 1e-9);
         }
+        assert!((norm(ship.speed) - ship.max_speed).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clamping_keeps_the_direction_of_the_speed() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.speed = (3.0 * ship.max_speed, 4.0 * ship.max_speed, 0.0);
+        ship.clamp_speed();
+
+        assert!((norm(ship.speed) - ship.max_speed).abs() < 1e-9);
+        assert!((ship.speed.1 / ship.speed.0 - 4.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn idle_ship_slows_down() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.speed = (10.0, 0.0, 0.0);
+        ship.update(1.0, &[], 1.0);
+        assert!(ship.speed.0 < 10.0 && ship.speed.0 > 0.0);
+
+        // Not while an engine fires
+        ship.engines.left = true;
+        ship.speed = (10.0, 0.0, 0.0);
+        ship.apply_drag(1.0);
+        assert_eq!(ship.speed, (10.0, 0.0, 0.0));
+    }
 
-        if self.rotation_engines.up {
-            self.pitch -= rotation_speed;
+    #[test]
+    fn empty_tank_stops_the_engines() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        let engines = &mut ship.engines;
+        engines.front = true;
+        engines.back = true;
+        engines.left = true;
+        engines.right = true;
+        engines.up = true;
+        engines.down = true;
+        let mut steps = 0;
+        while ship.fuel > 0.0 {
+            ship.accelerate(1.0 / 30.0);
+            steps += 1;
+            assert!(steps < 100 * 30, "the tank never empties");
         }
+        // Six engines at once empty it six times faster
+        assert!((steps as f64 / 30.0 - MAX_FUEL / (6.0 * FUEL_CONSUMPTION)).abs() < 0.1);
 
-        if self.rotation_engines.down {
-            self.pitch += rotation_speed;
+        ship.engines.front = false;
+        let speed = ship.speed;
+        for _ in 0..30 {
+            ship.accelerate(1.0 / 30.0);
         }
+        assert_eq!(ship.speed, speed);
+    }
+
+    #[test]
+    fn strafing_is_orthogonal_to_the_nose_when_pitched() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.orientation =
+            ship.orientation * Quaternion::from_axis_angle(LOCAL_LEFT, std::f64::consts::FRAC_PI_2);
+        ship.rotate(0.0);
+        ship.engines.left = true;
+        ship.accelerate(1.0 / 30.0);
+
+        let d = ship.direction;
+        let v = ship.speed;
+        assert!(norm(v) > 0.0);
+        assert!((d.0 * v.0 + d.1 * v.1 + d.2 * v.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rolling_turns_the_up_vector_around_the_nose() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.rotate(0.0);
+        let direction = ship.direction;
+        let up = ship.up;
+
+        ship.rotation_engines.roll_left = true;
+        // Already rolling at full speed
+        ship.angular_velocity = (0.0, 0.0, ship.rotation_engines.power);
+        ship.rotate(1.0);
+        let rolled = ship.rotation_engines.power;
 
-        // Update direction based on angle and pitch
-        self.direction.0 = self.angle.cos() * self.pitch.cos();
-        self.direction.1 = self.pitch.sin();
-        self.direction.2 = self.angle.sin() * self.pitch.cos();
+        let d = ship.direction;
+        assert!(norm((d.0 - direction.0, d.1 - direction.1, d.2 - direction.2)) < 1e-9);
+        let cos = ship.up.0 * up.0 + ship.up.1 * up.1 + ship.up.2 * up.2;
+        assert!((cos - rolled.cos()).abs() < 1e-9);
+        assert!((d.0 * ship.up.0 + d.1 * ship.up.1 + d.2 * ship.up.2).abs() < 1e-9);
+    }
+
+    /// Turn by `angle` with the rotation engine set by `set`, already spun up to the
+    /// full speed along `axis` (yaw, pitch and roll)
+    fn turn(ship: &mut TheShip, set: fn(&mut RotationEngines), axis: (f64, f64, f64), angle: f64) {
+        let engines = &mut ship.rotation_engines;
+        engines.left = false;
+        engines.right = false;
+        engines.up = false;
+        engines.down = false;
+        engines.roll_left = false;
+        engines.roll_right = false;
+        set(&mut ship.rotation_engines);
+        let power = ship.rotation_engines.power;
+        ship.angular_velocity = (axis.0 * power, axis.1 * power, axis.2 * power);
+        ship.rotate(angle / power);
+    }
+
+    fn assert_close(a: (f64, f64, f64), b: (f64, f64, f64)) {
+        assert!(
+            norm((a.0 - b.0, a.1 - b.1, a.2 - b.2)) < 1e-9,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn yaw_then_pitch_has_no_gimbal_lock() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+
+        turn(
+            &mut ship,
+            |engines| engines.left = true,
+            (1.0, 0.0, 0.0),
+            quarter,
+        );
+        assert_close(ship.direction, (1.0, 0.0, 0.0));
+
+        turn(
+            &mut ship,
+            |engines| engines.down = true,
+            (0.0, 1.0, 0.0),
+            quarter,
+        );
+        assert_close(ship.direction, (0.0, 1.0, 0.0));
+        assert_close(ship.up, (-1.0, 0.0, 0.0));
+
+        // Nose straight up, Euler angles would be locked here
+        turn(
+            &mut ship,
+            |engines| engines.left = true,
+            (1.0, 0.0, 0.0),
+            quarter,
+        );
+        assert_close(ship.direction, (0.0, 0.0, 1.0));
+        assert_close(ship.up, (-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn name_round_trips_through_to_json() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.name = checked_name(" Hearthian1\u{7}\n").unwrap();
+        assert_eq!(ship.to_json()["name"], "Hearthian1");
+
+        assert_eq!(checked_name("\t "), Err("empty_name"));
+        assert_eq!(checked_name(&"a".repeat(25)), Err("name_too_long"));
+    }
+
+    #[test]
+    fn engine_is_below_full_power_while_spooling_up() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.spool_time = 0.5;
+        ship.engines.front = true;
+        ship.accelerate(0.1);
+
+        let full_power = ship.engines.power * 0.1;
+        assert!(norm(ship.speed) > 0.0);
+        assert!(norm(ship.speed) < full_power);
+        assert!((ship.throttle.front - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn throttle_spools_up_then_down() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.spool_time = 0.5;
+        ship.engines.back = true;
+        ship.accelerate(0.25);
+        assert!((ship.throttle.back - 0.5).abs() < 1e-9);
+        ship.accelerate(0.5);
+        assert!((ship.throttle.back - 1.0).abs() < 1e-9);
+
+        ship.engines.back = false;
+        ship.accelerate(0.25);
+        assert!((ship.throttle.back - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn half_spooled_engine_reports_half_the_output() {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.spool_time = 0.5;
+        ship.engines.up = true;
+        ship.accelerate(0.25);
+
+        let output = &ship.to_json()["thruster_output"];
+        assert!((output["up"].as_f64().unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(output["down"], 0.0);
+
+        ship.fuel = 0.0;
+        ship.accelerate(0.25);
+        assert_eq!(ship.thruster_output, Throttle::default());
+    }
+
+    /// Speed gained in one step of the back engine at full throttle
+    fn back_thrust(boost: bool) -> f64 {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.spool_time = 0.0;
+        ship.engines.back = true;
+        ship.engines.boost = boost;
+        ship.accelerate(1.0 / 30.0);
+        norm(ship.speed)
+    }
 
-        // Normalize the direction
-        let norm =
-            (self.direction.0.powi(2) + self.direction.1.powi(2) + self.direction.2.powi(2)).sqrt();
-        self.direction.0 /= norm;
-        self.direction.1 /= norm;
-        self.direction.2 /= norm;
+    #[test]
+    fn boost_multiplies_the_thrust() {
+        assert!((back_thrust(true) - back_thrust(false) * BOOST_FACTOR).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overheated_boost_waits_for_the_cooldown() {
+        let delta_time = 1.0 / 30.0;
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.engines.back = true;
+        ship.engines.boost = true;
+        let mut elapsed = 0.0;
+        while ship.boost_cooldown == 0.0 {
+            assert!(ship.is_boosting());
+            ship.accelerate(delta_time);
+            elapsed += delta_time;
+        }
+        assert!((elapsed - BOOST_MAX_DURATION).abs() < delta_time);
+
+        // Still held, but blocked until the cooldown expires
+        let mut blocked = 0.0;
+        while !ship.is_boosting() {
+            ship.accelerate(delta_time);
+            blocked += delta_time;
+            assert!(blocked <= BOOST_COOLDOWN + delta_time);
+        }
+        assert!((blocked - BOOST_COOLDOWN).abs() < delta_time);
+        assert_eq!(ship.to_json()["boosting"], true);
+    }
+
+    #[test]
+    fn hard_impacts_damage_the_ship_and_gentle_ones_do_not() {
+        let impact = |speed: f64| {
+            let mut ship = TheShip::new_with_uuid(Uuid::nil());
+            // Just below the surface of a planet of radius 4 at the origin, falling into it
+            ship.position = (0.0, 0.0, 3.9);
+            ship.speed = (0.0, 0.0, -speed);
+            ship.collide_with_surface(
+                (0.0, 0.0, 0.0),
+                4.0,
+                (0.0, 0.0, 0.0),
+                SurfaceCollision::Stop,
+            );
+            ship
+        };
+
+        assert_eq!(impact(3.0).health, MAX_HEALTH);
+        let ship = impact(20.0);
+        assert!(ship.health < MAX_HEALTH);
+        assert!(!ship.is_destroyed());
+        assert!(impact(100.0).is_destroyed());
+
+        let mut first = TheShip::new_with_uuid(Uuid::nil());
+        let mut second = TheShip::new_with_uuid(Uuid::nil());
+        first.position = (-1.0, 0.0, 0.0);
+        second.position = (1.0, 0.0, 0.0);
+        first.speed = (15.0, 0.0, 0.0);
+        second.speed = (-15.0, 0.0, 0.0);
+        first.collide_with(&mut second);
+        assert!(first.health < MAX_HEALTH);
+        assert!(second.health < MAX_HEALTH);
+    }
+
+    #[test]
+    fn released_rotation_engines_stop_the_ship_turning() {
+        let delta_time = 1.0 / 30.0;
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.rotation_engines.left = true;
+        for _ in 0..30 {
+            ship.rotate(delta_time);
+        }
+        assert!(ship.angular_velocity.0 > 0.0);
+
+        ship.rotation_engines.left = false;
+        let mut previous = ship.angular_velocity.0;
+        for _ in 0..300 {
+            ship.rotate(delta_time);
+            assert!(ship.angular_velocity.0 <= previous);
+            previous = ship.angular_velocity.0;
+        }
+
+        assert_eq!(ship.angular_velocity, (0.0, 0.0, 0.0));
+        let direction = ship.direction;
+        ship.rotate(delta_time);
+        assert_close(ship.direction, direction);
+    }
+
+    #[test]
+    fn degenerate_orientation_keeps_a_unit_direction() {
+        for degenerate in [0.0, f64::NAN] {
+            let mut ship = TheShip::new_with_uuid(Uuid::nil());
+            ship.orientation = Quaternion {
+                w: degenerate,
+                x: degenerate,
+                y: degenerate,
+                z: degenerate,
+            };
+            ship.rotation_engines.left = true;
+            ship.rotate(1.0 / 30.0);
+
+            let d = ship.direction;
+            assert!(
+                d.0.is_finite() && d.1.is_finite() && d.2.is_finite(),
+                "{:?}",
+                d
+            );
+            assert!((norm(d) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn ship_crossing_the_edge_of_the_world_is_brought_back() {
+        let crossing = |boundary: WorldBoundary| {
+            let mut ship = TheShip::new_with_uuid(Uuid::nil());
+            ship.position = (0.0, 0.0, 1010.0);
+            ship.speed = (0.0, 0.0, 5.0);
+            ship.keep_within(1000.0, boundary, 1.0);
+            ship
+        };
+
+        let repelled = crossing(WorldBoundary::Repel);
+        assert_eq!(repelled.position, (0.0, 0.0, 1010.0));
+        assert!(repelled.speed.2 < 5.0, "{:?}", repelled.speed);
+
+        let wrapped = crossing(WorldBoundary::Wrap);
+        assert_eq!(wrapped.position, (0.0, 0.0, -1000.0));
+        assert_eq!(wrapped.speed, (0.0, 0.0, 5.0));
+
+        let mut inside = TheShip::new_with_uuid(Uuid::nil());
+        inside.position = (0.0, 0.0, 990.0);
+        inside.speed = (0.0, 0.0, 5.0);
+        inside.keep_within(1000.0, WorldBoundary::Wrap, 1.0);
+        assert_eq!(inside.position, (0.0, 0.0, 990.0));
+        assert_eq!(inside.speed, (0.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn leapfrog_orbit_drifts_less_than_euler() {
+        let sun = Attractor {
+            position: (0.0, 0.0, 0.0),
+            mass: 10000.0,
+            softening: DEFAULT_GRAVITY_SOFTENING,
+        };
+        let start = (100.0, 0.0, 0.0);
+        // Circular speed at the start, the drag then makes the ship spiral in slowly
+        let pull = sun.pull(start, 1.0);
+        let orbital_speed = (dot(pull, pull).sqrt() * 100.0).sqrt();
+        let radius_after = |integrator: Integrator, delta_time: f64, steps: u32| {
+            let mut ship = TheShip::new_with_uuid(Uuid::nil());
+            ship.integrator = integrator;
+            ship.position = start;
+            ship.speed = (0.0, orbital_speed, 0.0);
+            for _ in 0..steps {
+                ship.update(delta_time, &[sun], 1.0);
+            }
+            ship.distance_to(sun.position)
+        };
+
+        // 20 seconds, about a third of a revolution
+        let reference = radius_after(Integrator::Leapfrog, 0.0025, 8000);
+        let euler = (radius_after(Integrator::Euler, 0.25, 80) - reference).abs();
+        let leapfrog = (radius_after(Integrator::Leapfrog, 0.25, 80) - reference).abs();
+        assert!(leapfrog < euler, "leapfrog {} euler {}", leapfrog, euler);
+    }
+
+    /// At the spawn point, at rest, with engines reaching full thrust at once
+    fn ship() -> TheShip {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.spool_time = 0.0;
+        ship
+    }
+
+    /// Speed gained in one step with only the engine set by `fire`
+    fn thrust(fire: impl Fn(&mut Engines)) -> (TheShip, (f64, f64, f64)) {
+        let mut ship = ship();
+        fire(&mut ship.engines);
+        ship.accelerate(DELTA_TIME);
+        let speed = ship.speed;
+        (ship, speed)
+    }
+
+    /// `TheShip::to_json` as it was hand-built, before it relied on the derived `Serialize`
+    fn legacy_json(ship: &TheShip) -> serde_json::Value {
+        json!({
+            "uuid": ship.uuid.to_string(),
+            "name": ship.name,
+            "speed": ship.speed,
+            "position": ship.position,
+            "prev_position": ship.prev_position,
+            "direction": ship.direction,
+            "up": ship.up,
+            "angle": ship.angle,
+            "pitch": ship.pitch,
+            "roll": ship.roll,
+            "angular_velocity": ship.angular_velocity,
+            "engines": {
+                "power": ship.engines.power,
+                "front": ship.engines.front,
+                "back": ship.engines.back,
+                "left": ship.engines.left,
+                "right": ship.engines.right,
+                "up": ship.engines.up,
+                "down": ship.engines.down,
+                "boost": ship.engines.boost,
+            },
+            "boosting": ship.is_boosting(),
+            "boost_cooldown": ship.boost_cooldown,
+            "throttle": {
+                "front": ship.throttle.front,
+                "back": ship.throttle.back,
+                "left": ship.throttle.left,
+                "right": ship.throttle.right,
+                "up": ship.throttle.up,
+                "down": ship.throttle.down,
+            },
+            "thruster_output": ship.thruster_output,
+            "rotation_engines": {
+                "power": ship.rotation_engines.power,
+                "left": ship.rotation_engines.left,
+                "right": ship.rotation_engines.right,
+                "up": ship.rotation_engines.up,
+                "down": ship.rotation_engines.down,
+                "roll_left": ship.rotation_engines.roll_left,
+                "roll_right": ship.rotation_engines.roll_right,
+            },
+            "fuel": ship.fuel,
+            "max_fuel": MAX_FUEL,
+            "landed_on": ship.landed_on,
+            "autopilot": ship.autopilot,
+            "orbit": ship.orbit.as_ref().map(|orbit| json!({
+                "target": orbit.target,
+                "radius": orbit.radius,
+            })),
+            "health": ship.health,
+            "max_health": MAX_HEALTH,
+            "destroyed": ship.is_destroyed(),
+            "respawn_in": ship.respawn_in,
+            "last_seq": ship.last_seq,
+            "trail": ship.trail,
+        })
+    }
+
+    #[test]
+    fn to_json_keeps_every_field_of_the_hand_built_json() {
+        let mut ship = ship();
+        ship.trail.push_back(ship.position);
+        let json = ship.to_json();
+        let legacy = legacy_json(&ship);
+
+        for (key, value) in legacy.as_object().unwrap() {
+            assert_eq!(json.get(key), Some(value), "{}", key);
+        }
+    }
+
+    #[test]
+    fn back_and_front_engines_push_along_the_nose() {
+        let (ship, speed) = thrust(|engines| engines.back = true);
+        let power = ship.engines.power * DELTA_TIME;
+        assert_close(
+            speed,
+            (
+                ship.direction.0 * power,
+                ship.direction.1 * power,
+                ship.direction.2 * power,
+            ),
+        );
+
+        let (ship, speed) = thrust(|engines| engines.front = true);
+        assert_close(
+            speed,
+            (
+                -ship.direction.0 * power,
+                -ship.direction.1 * power,
+                -ship.direction.2 * power,
+            ),
+        );
+    }
+
+    #[test]
+    fn vertical_engines_push_along_the_up_axis() {
+        let (ship, speed) = thrust(|engines| engines.down = true);
+        assert!(dot(speed, ship.up) > 0.0);
+        assert!(dot(speed, ship.direction).abs() < EPSILON);
+
+        let (ship, speed) = thrust(|engines| engines.up = true);
+        assert!(dot(speed, ship.up) < 0.0);
+        assert!(dot(speed, ship.direction).abs() < EPSILON);
+    }
+
+    #[test]
+    fn lateral_engines_push_across_the_nose_and_the_up_axis() {
+        let (ship, speed) = thrust(|engines| engines.left = true);
+        let lateral = normalized_cross(ship.direction, ship.up);
+        assert!(dot(speed, lateral) > 0.0);
+        assert!(dot(speed, ship.direction).abs() < EPSILON);
+        assert!(dot(speed, ship.up).abs() < EPSILON);
+
+        let (ship, speed) = thrust(|engines| engines.right = true);
+        assert!(dot(speed, normalized_cross(ship.direction, ship.up)) < 0.0);
+    }
+
+    #[test]
+    fn rotation_keeps_the_axes_orthonormal() {
+        let mut ship = ship();
+        ship.rotation_engines.left = true;
+        ship.rotation_engines.up = true;
+        ship.rotation_engines.roll_right = true;
+        for _ in 0..1000 {
+            ship.rotate(DELTA_TIME);
+        }
+
+        assert!((norm(ship.direction) - 1.0).abs() < EPSILON);
+        assert!((norm(ship.up) - 1.0).abs() < EPSILON);
+        assert!(dot(ship.direction, ship.up).abs() < EPSILON);
+    }
+
+    #[test]
+    fn yaw_turns_the_nose_around_the_up_axis() {
+        let mut ship = ship();
+        let (direction, up) = (ship.direction, ship.up);
+        ship.rotation_engines.left = true;
+        ship.rotate(DELTA_TIME);
+
+        assert!(dot(ship.direction, direction) < 1.0 - EPSILON);
+        assert_close(ship.up, up);
+        assert!(dot(ship.direction, up).abs() < EPSILON);
+
+        // The other way round
+        let mut other = self::ship();
+        other.rotation_engines.right = true;
+        other.rotate(DELTA_TIME);
+        let lateral = normalized_cross(direction, up);
+        assert!(dot(ship.direction, lateral) * dot(other.direction, lateral) < 0.0);
+    }
+
+    #[test]
+    fn pitch_turns_the_nose_around_the_lateral_axis() {
+        let mut ship = ship();
+        let lateral = normalized_cross(ship.direction, ship.up);
+        let up = ship.up;
+        ship.rotation_engines.up = true;
+        ship.rotate(DELTA_TIME);
+
+        assert!(dot(ship.direction, up).abs() > EPSILON);
+        assert_close(normalized_cross(ship.direction, ship.up), lateral);
+    }
+
+    #[test]
+    fn update_moves_the_ship_along_its_speed() {
+        for integrator in [Integrator::Euler, Integrator::Leapfrog] {
+            let mut ship = ship();
+            ship.integrator = integrator;
+            ship.speed = (3.0, -2.0, 1.0);
+            let start = ship.position;
+            ship.update(DELTA_TIME, &[], 1.0);
+
+            // Drag first, the position follows the damped speed
+            let factor = 1.0 - LINEAR_DRAG * DELTA_TIME;
+            assert_close(ship.speed, (3.0 * factor, -2.0 * factor, 1.0 * factor));
+            assert_close(
+                ship.position,
+                (
+                    start.0 + ship.speed.0 * DELTA_TIME,
+                    start.1 + ship.speed.1 * DELTA_TIME,
+                    start.2 + ship.speed.2 * DELTA_TIME,
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn update_falls_towards_an_attractor() {
+        for integrator in [Integrator::Euler, Integrator::Leapfrog] {
+            let mut ship = ship();
+            ship.integrator = integrator;
+            let attractor = Attractor {
+                position: (ship.position.0 + 100.0, ship.position.1, ship.position.2),
+                mass: 1000.0,
+                softening: DEFAULT_GRAVITY_SOFTENING,
+            };
+            ship.update(DELTA_TIME, &[attractor], 1.0);
+
+            assert!(ship.speed.0 > 0.0);
+            assert!(ship.speed.1.abs() < EPSILON && ship.speed.2.abs() < EPSILON);
+        }
+    }
+
+    /// Kinetic plus potential energy of the ship around `attractor`, same softening as the pull
+    fn energy(ship: &TheShip, attractor: &Attractor) -> f64 {
+        let distance = ship.distance_to(attractor.position);
+        let potential = -attractor.mass / (distance.powi(2) + attractor.softening.powi(2)).sqrt();
+        dot(ship.speed, ship.speed) / 2.0 + potential
+    }
+
+    #[test]
+    fn subdivided_gravity_conserves_the_energy_of_a_close_fly_by() {
+        let attractor = Attractor {
+            position: (0.0, 0.0, 0.0),
+            mass: 3000.0,
+            softening: DEFAULT_GRAVITY_SOFTENING,
+        };
+        // Passing 6 units from the body, steps of a dropped frame
+        let delta_time = 0.25;
+        for integrator in [Integrator::Euler, Integrator::Leapfrog] {
+            let mut start = ship();
+            start.integrator = integrator;
+            start.max_speed = f64::INFINITY;
+            // Without drag, only the integration changes the energy
+            start.linear_drag = 0.0;
+            start.position = (-60.0, 6.0, 0.0);
+            start.speed = (20.0, 0.0, 0.0);
+            let expected = energy(&start, &attractor);
+
+            let error_after_fly_by = |subdivided: bool| {
+                let mut ship = start.clone();
+                for _ in 0..24 {
+                    let substeps = if subdivided {
+                        ship.gravity_substeps(delta_time, &[attractor])
+                    } else {
+                        1
+                    };
+                    ship.integrate(delta_time, substeps, &[attractor], 1.0);
+                }
+                assert!(ship.position.0 > 40.0, "still in the encounter");
+                (energy(&ship, &attractor) - expected).abs()
+            };
+            let single = error_after_fly_by(false);
+            let subdivided = error_after_fly_by(true);
+            assert!(
+                subdivided < single / 5.0,
+                "{:?}: energy error {} subdivided, {} in single steps",
+                integrator,
+                subdivided,
+                single
+            );
+        }
+    }
+
+    #[test]
+    fn fast_outbound_ship_has_escaped() {
+        let sun = OrbitedBody {
+            name: "Sun",
+            attractor: Attractor {
+                position: (0.0, 0.0, 0.0),
+                mass: 10000.0,
+                softening: DEFAULT_GRAVITY_SOFTENING,
+            },
+            velocity: (0.0, 0.0, 0.0),
+        };
+        let mut ship = ship();
+        ship.position = (100.0, 0.0, 0.0);
+        // Escape velocity of sqrt(2 * 10000 / 100), about 14.1
+        ship.speed = (20.0, 0.0, 0.0);
+        ship.read_orbit(&[sun], 1.0);
+        assert!(ship.escaped);
+        assert_eq!(ship.to_json()["escaped"], true);
+
+        ship.speed = (10.0, 0.0, 0.0);
+        ship.read_orbit(&[sun], 1.0);
+        assert!(!ship.escaped, "too slow to escape");
+
+        ship.speed = (-20.0, 0.0, 0.0);
+        ship.read_orbit(&[sun], 1.0);
+        assert!(!ship.escaped, "heading back to the sun");
+    }
+
+    #[test]
+    fn circular_orbit_has_no_eccentricity() {
+        // Moving with the planet, the orbit is around it whatever its velocity
+        let planet = OrbitedBody {
+            name: "Earth",
+            attractor: Attractor {
+                position: (90.0, 0.0, 0.0),
+                mass: 200.0,
+                softening: DEFAULT_GRAVITY_SOFTENING,
+            },
+            velocity: (0.0, 5.0, 0.0),
+        };
+        let mut ship = ship();
+        ship.position = (100.0, 0.0, 0.0);
+        // Circular speed sqrt(200 / 10)
+        ship.speed = (0.0, 5.0, 20.0_f64.sqrt());
+        ship.read_orbit(&[planet], 1.0);
+
+        match ship.orbital_elements {
+            Some(OrbitalElements::Elliptic {
+                ref body,
+                semi_major_axis,
+                eccentricity,
+                periapsis,
+                apoapsis,
+            }) => {
+                assert_eq!(body, "Earth");
+                assert!(eccentricity < 1e-6, "eccentricity {}", eccentricity);
+                assert!((semi_major_axis - 10.0).abs() < 1e-6);
+                assert!((periapsis - 10.0).abs() < 1e-6);
+                assert!((apoapsis - periapsis).abs() < 1e-6);
+            }
+            ref other => panic!("not an elliptic orbit: {:?}", other),
+        }
+        assert!(!ship.escaped);
+    }
+
+    #[test]
+    fn softened_pull_stays_bounded_at_the_center() {
+        let attractor = Attractor {
+            position: (0.0, 0.0, 0.0),
+            mass: 10000.0,
+            softening: 20.0,
+        };
+        // Plummer: the strongest pull, at softening / sqrt(2), is below G * m / softening²
+        let bound = attractor.mass / attractor.softening.powi(2);
+        for distance in [0.0, 1e-9, 0.01, 1.0, 10.0, 14.0, 20.0] {
+            let pull = norm(attractor.pull((distance, 0.0, 0.0), 1.0));
+            assert!(pull.is_finite(), "{} at {}", pull, distance);
+            assert!(pull <= bound, "{} at {}", pull, distance);
+        }
+        assert_eq!(attractor.pull((0.0, 0.0, 0.0), 1.0), (0.0, 0.0, 0.0));
     }
 }
@@ -0,0 +1,1198 @@
+use crate::compression;
+use crate::connections::{Connections, Directive};
+use crate::delta::DeltaEncoder;
+use crate::messages::{
+    self, ClientCommand, ClientMessage, Encoding, ServerMessage, StatePayload, TimeSyncRequest,
+    PROTOCOL_VERSION,
+};
+use crate::metrics::Metrics;
+use crate::rate_limiter::RateLimiter;
+use crate::rooms::{Room, Rooms, DEFAULT_ROOM};
+use crate::sessions::SessionCache;
+use crate::solar_system::{self, SolarSystem};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::reply::{Reply, Response};
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Filter;
+
+/// Chat messages a ship can send per second, on top of the limit of the commands
+const CHAT_MESSAGES_PER_SECOND: u32 = 1;
+
+/// What every connection shares with the rest of the server
+#[derive(Clone)]
+pub struct SocketContext {
+    pub rooms: Arc<Rooms>,
+    pub connections: Arc<Connections>,
+    pub sessions: Arc<parking_lot::Mutex<SessionCache>>,
+    pub shutdown: watch::Receiver<bool>, // becomes true when the server stops
+    pub metrics: Arc<Metrics>,
+    pub max_commands_per_second: u32,
+    pub chat_range: Option<f64>, // distance at which the ships hear a chat message
+    pub keyframe_interval: u32,  // frames between two full states for the delta clients
+    pub broadcast_stride: u64,   // snapshots between two frames sent to a client
+    pub ping_interval: Duration,
+    pub pong_timeout: Duration, // a client silent for longer is dropped
+    pub replaying: bool,        // `REPLAY_PATH`, the recording has no ship of the client
+}
+
+/// Query parameters of the upgrade request, e.g. `/socket?delta=true&encoding=msgpack`
+#[derive(Debug, Default, Deserialize)]
+pub struct SocketOptions {
+    /// Token given in the welcome of a previous connection, to get its ship back
+    pub session: Option<Uuid>,
+    /// Solar system to join, the default room when not given
+    pub room: Option<String>,
+    #[serde(default)]
+    pub delta: bool,
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Deflate the large frames, see `compression::compress`
+    #[serde(default)]
+    pub compress: bool,
+    /// Version of the protocol the client speaks, see `messages::PROTOCOL_VERSION`
+    pub protocol_version: Option<u32>,
+}
+
+/// Start of the subprotocols of the server, followed by the version and the features
+/// joined by `+`, e.g. `owweb.v1.binary+delta`
+const SUBPROTOCOL_PREFIX: &str = "owweb.v";
+
+impl SocketOptions {
+    /// Take the first of the subprotocols of the `Sec-WebSocket-Protocol` header the server
+    /// speaks, and enable its features on top of the query parameters. Its name, to echo back
+    /// in the handshake, `None` when the server speaks none of them
+    pub fn negotiate(&mut self, requested: &str) -> Option<String> {
+        let (name, features) = requested
+            .split(',')
+            .map(str::trim)
+            .find_map(|name| Some((name, parse_subprotocol(name)?)))?;
+
+        self.protocol_version = Some(PROTOCOL_VERSION);
+        for feature in features {
+            match feature {
+                Feature::Binary => self.encoding = Encoding::MessagePack,
+                Feature::Delta => self.delta = true,
+                Feature::Compress => self.compress = true,
+            }
+        }
+        Some(name.to_string())
+    }
+}
+
+/// What a subprotocol can enable, each one as its query parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Feature {
+    Binary, // `encoding=msgpack`
+    Delta,
+    Compress,
+}
+
+impl Feature {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "binary" => Some(Self::Binary),
+            "delta" => Some(Self::Delta),
+            "compress" => Some(Self::Compress),
+            _ => None,
+        }
+    }
+}
+
+/// The features of a subprotocol of the version of the server, `None` for any other
+/// subprotocol or one with an unknown feature
+fn parse_subprotocol(name: &str) -> Option<Vec<Feature>> {
+    let rest = name.strip_prefix(SUBPROTOCOL_PREFIX)?;
+    let (version, features) = rest.split_once('.').unwrap_or((rest, ""));
+    if version.parse::<u32>().ok()? != PROTOCOL_VERSION {
+        return None;
+    }
+    if features.is_empty() {
+        return Some(Vec::new());
+    }
+    features.split('+').map(Feature::parse).collect()
+}
+
+/// State of one client connection
+struct Connection {
+    context: SocketContext,
+    room: Arc<Room>,
+    ship_uuid: Uuid,
+    session: Uuid,
+    rate_limiter: RateLimiter,
+    rate_limited: bool, // the client was already told it is over the limit
+    chat_limiter: RateLimiter,
+    last_pong: Instant,
+}
+
+/// The game socket, upgraded at /socket
+pub fn route(
+    context: SocketContext,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("socket")
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(warp::query::<SocketOptions>())
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .map(move |ws, options, requested| upgrade(ws, options, requested, context.clone()))
+}
+
+/// Upgrade the request to a socket served by `handle_connection`. A client asking for
+/// subprotocols gets one of them in the handshake, or an `unsupported_subprotocol` error
+pub fn upgrade(
+    ws: Ws,
+    mut options: SocketOptions,
+    requested: Option<String>,
+    context: SocketContext,
+) -> Response {
+    let subprotocol = match requested.map(|requested| options.negotiate(&requested)) {
+        Some(Some(subprotocol)) => Some(subprotocol),
+        Some(None) => {
+            return warp::reply::with_status(
+                warp::reply::json(&json!({"error": "unsupported_subprotocol"})),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response()
+        }
+        None => None,
+    };
+
+    let reply = ws.on_upgrade(move |socket| handle_connection(socket, options, context));
+    match subprotocol {
+        Some(subprotocol) => {
+            warp::reply::with_header(reply, "sec-websocket-protocol", subprotocol).into_response()
+        }
+        None => reply.into_response(),
+    }
+}
+
+/// Serve one client from the upgrade until the socket closes, its ship lives as long as the connection
+pub async fn handle_connection(socket: WebSocket, options: SocketOptions, context: SocketContext) {
+    if let Err(reason) = messages::check_protocol_version(options.protocol_version) {
+        return reject(socket, reason, options.encoding).await;
+    }
+    let room_name = options.room.as_deref().unwrap_or(DEFAULT_ROOM);
+    if !Rooms::is_valid_name(room_name) {
+        return reject(socket, "invalid_room", options.encoding).await;
+    }
+
+    let mut restored = options
+        .session
+        .and_then(|session| context.sessions.lock().take(session));
+    let session = options
+        .session
+        .filter(|_| restored.is_some())
+        .unwrap_or_else(Uuid::new_v4);
+
+    let (room, ship_uuid) = match context.rooms.join(room_name, &mut restored) {
+        Some(joined) => joined,
+        None => {
+            // Still there for the next attempt
+            if let Some(ship) = restored {
+                context.sessions.lock().store(session, ship);
+            }
+            return reject(socket, "server_full", options.encoding).await;
+        }
+    };
+    println!(
+        "Websocket opened. Ship uuid {} in room {}",
+        ship_uuid, room.name
+    );
+    context.metrics.client_connected();
+    let encoding = options.encoding;
+    let compress = options.compress;
+    let to_frame = |message: &ServerMessage| {
+        let frame = message.to_message(encoding);
+        if compress {
+            compression::compress(frame)
+        } else {
+            frame
+        }
+    };
+    let broadcast_stride = context.broadcast_stride;
+    let replaying = context.replaying;
+
+    let mut snapshots = room.snapshots.subscribe();
+    let mut directives = context.connections.register(ship_uuid);
+    let mut shutdown = context.shutdown.clone();
+    let mut heartbeat = tokio::time::interval(context.ping_interval);
+    let pong_timeout = context.pong_timeout;
+    let mut delta_encoder = options
+        .delta
+        .then(|| DeltaEncoder::new(context.keyframe_interval));
+
+    let mut connection = Connection {
+        rate_limiter: RateLimiter::new(context.max_commands_per_second),
+        context,
+        room,
+        ship_uuid,
+        session,
+        rate_limited: false,
+        chat_limiter: RateLimiter::new(CHAT_MESSAGES_PER_SECOND),
+        last_pong: Instant::now(),
+    };
+
+    let (mut outgoing, mut incoming) = socket.split();
+    let welcome = ServerMessage::Welcome {
+        uuid: ship_uuid,
+        session,
+        protocol_version: PROTOCOL_VERSION,
+    };
+    let mut close_frame = None;
+
+    if outgoing.send(to_frame(&welcome)).await.is_ok() {
+        loop {
+            tokio::select! {
+                message = incoming.next() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => {
+                            eprintln!("WebSocket error for ship {}: {}", ship_uuid, e);
+                            break;
+                        }
+                        None => break,
+                    };
+
+                    if message.is_close() {
+                        close_frame = message
+                            .close_frame()
+                            .map(|(code, reason)| (code, reason.to_string()));
+                        break;
+                    }
+
+                    if let Some(reply) = connection.on_message(message).await {
+                        if outgoing.send(to_frame(&reply)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    // The TCP connection may be gone without the socket knowing it
+                    if connection.last_pong.elapsed() > pong_timeout {
+                        eprintln!("No pong from ship {}, closing its socket", ship_uuid);
+                        break;
+                    }
+                    if outgoing.send(Message::ping(Vec::new())).await.is_err() {
+                        break;
+                    }
+                }
+                directive = directives.recv() => match directive {
+                    Some(Directive::Message(message)) => {
+                        if outgoing.send(to_frame(&message)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Directive::Close) | None => {
+                        let _ = outgoing.send(Message::close()).await;
+                        break;
+                    }
+                },
+                _ = shutdown.changed() => {
+                    let _ = outgoing.send(to_frame(&ServerMessage::Shutdown)).await;
+                    let _ = outgoing.send(Message::close()).await;
+                    break;
+                }
+                snapshot = snapshots.recv() => {
+                    let snapshot = match snapshot {
+                        Ok(snapshot) => snapshot,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            eprintln!("Ship {} skipped {} snapshots", ship_uuid, skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    // Not on the tick, which stops while the room is paused
+                    if snapshot.frame % broadcast_stride != 0 {
+                        continue;
+                    }
+
+                    // Envoyer les informations des planètes et du vaisseau via la websocket
+                    // The ship shows up from the tick following its creation, a replay
+                    // is watched without one
+                    let ship = snapshot.ships.iter().find(|ship| ship.uuid == ship_uuid);
+                    let ship = match ship {
+                        Some(ship) => ship.to_json(),
+                        None if replaying => serde_json::Value::Null,
+                        None => continue,
+                    };
+                    let message = match &mut delta_encoder {
+                        Some(encoder) => encoder.encode(&snapshot, ship),
+                        None => ServerMessage::State {
+                            payload: StatePayload::new(&snapshot, ship),
+                        },
+                    };
+                    if outgoing.send(to_frame(&message)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Unregistered with it, the ship can be reached only while its socket is open
+    drop(directives);
+    connection.on_close(close_frame).await;
+}
+
+/// The ship only knows the names of the bodies it targets, they are checked here
+fn check_targets(command: &ClientCommand, solar_system: &SolarSystem) -> Result<(), &'static str> {
+    let autopilot_target = command
+        .autopilot
+        .as_ref()
+        .and_then(|autopilot| autopilot.target.as_deref());
+    if autopilot_target.is_some_and(|target| !solar_system.has_body(target)) {
+        return Err("unknown_target");
+    }
+
+    if let Some(orbit) = &command.orbit {
+        let body_radius = solar_system
+            .body_radius(&orbit.target)
+            .ok_or("unknown_target")?;
+        if !orbit.radius.is_finite() || orbit.radius <= body_radius {
+            return Err("invalid_radius");
+        }
+    }
+    Ok(())
+}
+
+/// Refuse the connection before it gets a ship
+async fn reject(mut socket: WebSocket, reason: &str, encoding: Encoding) {
+    let error = ServerMessage::Error {
+        reason: reason.to_string(),
+    };
+    let _ = socket.send(error.to_message(encoding)).await;
+    let _ = socket.send(Message::close()).await;
+}
+
+impl Connection {
+    /// Handle one frame of the client, returns the reply to send back if any
+    async fn on_message(&mut self, message: Message) -> Option<ServerMessage> {
+        if message.is_pong() {
+            self.last_pong = Instant::now();
+            return None;
+        }
+        // Answered by warp itself
+        if message.is_ping() {
+            return None;
+        }
+
+        if !self.rate_limiter.try_acquire() {
+            if !self.rate_limited {
+                self.rate_limited = true;
+                return Some(ServerMessage::Error {
+                    reason: "rate_limited".to_string(),
+                });
+            }
+            return None;
+        }
+        self.rate_limited = false;
+        self.context.metrics.message_processed();
+
+        let msg_text = match message.to_str() {
+            Ok(text) => text,
+            Err(()) => {
+                eprintln!("Ignoring non-text frame from ship {}", self.ship_uuid);
+                return None;
+            }
+        };
+
+        let data = match serde_json::from_str::<serde_json::Value>(msg_text) {
+            Ok(data) => data,
+            Err(e) => {
+                // Debug builds only, a client sending garbage would flood the log
+                if cfg!(debug_assertions) {
+                    eprintln!("Invalid JSON from ship {}: {}", self.ship_uuid, e);
+                }
+                return Some(ServerMessage::Error {
+                    reason: "invalid_json".to_string(),
+                });
+            }
+        };
+        if data.get("type").and_then(serde_json::Value::as_str) == Some("time_sync") {
+            let request = serde_json::from_value::<TimeSyncRequest>(data).ok();
+            return Some(ServerMessage::TimeSync {
+                client_time_ms: request.and_then(|request| request.client_time_ms),
+                server_time_ms: self.context.rooms.server_time_ms(),
+                tick: self.room.latest().tick,
+            });
+        }
+        match serde_json::from_value::<ClientMessage>(data) {
+            Ok(ClientMessage {
+                data: Some(command),
+            }) => {
+                let chat_text = command.chat_text();
+                let result = {
+                    let solar_system = self.room.solar_system.read();
+                    check_targets(&command, &solar_system).and_then(|()| {
+                        match command.client_time_ms {
+                            // In the order the client sent them, see `Room::apply_commands`
+                            Some(client_time_ms) => {
+                                self.room
+                                    .buffer_command(self.ship_uuid, client_time_ms, command);
+                                Ok(())
+                            }
+                            None => match solar_system.ships.get(&self.ship_uuid) {
+                                Some(ship) => command.apply(&mut ship.lock()),
+                                None => Ok(()),
+                            },
+                        }
+                    })
+                };
+                let result = match chat_text {
+                    Some(text) => result.and(text).and_then(|text| self.chat(text)),
+                    None => result,
+                };
+
+                result.err().map(|reason| ServerMessage::Error {
+                    reason: reason.to_string(),
+                })
+            }
+            Ok(ClientMessage { data: None }) => Some(ServerMessage::Error {
+                reason: "missing_data".to_string(),
+            }),
+            Err(e) => {
+                eprintln!("Invalid command from ship {}: {}", self.ship_uuid, e);
+                Some(ServerMessage::Error {
+                    reason: "invalid_command".to_string(),
+                })
+            }
+        }
+    }
+
+    /// Relay the message to every ship of the room in range, the sender included
+    fn chat(&mut self, text: String) -> Result<(), &'static str> {
+        if !self.chat_limiter.try_acquire() {
+            return Err("chat_rate_limited");
+        }
+
+        let solar_system = self.room.solar_system.read();
+        let (from, position) = match solar_system.ships.get(&self.ship_uuid) {
+            Some(ship) => {
+                let ship = ship.lock();
+                (ship.name.clone(), ship.position)
+            }
+            None => return Ok(()),
+        };
+        let ts = self.context.rooms.server_time_ms();
+        for (&uuid, ship) in &solar_system.ships {
+            let in_range = match self.context.chat_range {
+                Some(range) if uuid != self.ship_uuid => {
+                    solar_system::distance(position, ship.lock().position) <= range
+                }
+                _ => true,
+            };
+            if !in_range {
+                continue;
+            }
+            let message = ServerMessage::Chat {
+                from: from.clone(),
+                text: text.clone(),
+                ts,
+            };
+            self.context.connections.send_message(uuid, message);
+        }
+        Ok(())
+    }
+
+    async fn on_close(&mut self, close_frame: Option<(u16, String)>) {
+        self.context.connections.unregister(self.ship_uuid);
+        let ship = self.room.solar_system.write().remove_ship(self.ship_uuid);
+
+        if let Some(ship) = ship {
+            let mut ship = ship.lock().clone();
+            ship.release_controls();
+            self.context.sessions.lock().store(self.session, ship);
+        }
+        self.context.metrics.client_disconnected();
+        println!(
+            "WebSocket closing for ship {} ({:?})",
+            self.ship_uuid, close_frame
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::TheShip;
+    use crate::solar_system::{Snapshot, SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+
+    fn context(shutdown: watch::Receiver<bool>) -> SocketContext {
+        SocketContext {
+            rooms: Arc::new(Rooms::new(
+                SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+                None,
+            )),
+            connections: Arc::new(Connections::default()),
+            sessions: Arc::new(parking_lot::Mutex::new(SessionCache::new(
+                Duration::from_secs(30),
+            ))),
+            metrics: Arc::new(Metrics::new()),
+            max_commands_per_second: 120,
+            chat_range: None,
+            keyframe_interval: 30,
+            broadcast_stride: 1,
+            ping_interval: Duration::from_secs(5),
+            pong_timeout: Duration::from_secs(15),
+            replaying: false,
+            shutdown,
+        }
+    }
+
+    /// Next text frame, skipping the pings of the heartbeat
+    async fn next_frame(client: &mut warp::test::WsClient) -> serde_json::Value {
+        loop {
+            let message = client.recv().await.unwrap();
+            if !message.is_ping() {
+                return serde_json::from_str(message.to_str().unwrap()).unwrap();
+            }
+        }
+    }
+
+    /// Until the server closes the socket, skipping the pings of the heartbeat
+    async fn wait_for_close(client: &mut warp::test::WsClient) {
+        while let Ok(message) = client.recv().await {
+            assert!(message.is_ping(), "{:?} before the close", message);
+        }
+    }
+
+    /// `route`, with a signal each time a connection task returns
+    fn watched_route(
+        context: SocketContext,
+    ) -> (
+        impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone,
+        mpsc::UnboundedReceiver<()>,
+    ) {
+        let (ended, receiver) = mpsc::unbounded_channel();
+        let route = warp::path("socket")
+            .and(warp::ws())
+            .and(warp::query::<SocketOptions>())
+            .map(move |ws: Ws, options| {
+                let (context, ended) = (context.clone(), ended.clone());
+                ws.on_upgrade(move |socket| async move {
+                    handle_connection(socket, options, context).await;
+                    let _ = ended.send(());
+                })
+            });
+        (route, receiver)
+    }
+
+    /// Until one more connection task returned
+    async fn connection_ended(ended: &mut mpsc::UnboundedReceiver<()>) {
+        tokio::time::timeout(Duration::from_secs(10), ended.recv())
+            .await
+            .expect("the connection outlived its socket");
+    }
+
+    #[tokio::test]
+    async fn client_plays_over_the_warp_socket() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let (route, mut ended) = watched_route(context.clone());
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route.clone())
+            .await
+            .unwrap();
+
+        let welcome = next_frame(&mut client).await;
+        assert_eq!(welcome["type"], "welcome");
+        let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+
+        client
+            .send_text(r#"{"data": {"engines": {"front": true}}}"#)
+            .await;
+        client.send_text(r#"{"data": {"engines": 1}}"#).await;
+        assert_eq!(
+            next_frame(&mut client).await,
+            serde_json::json!({"type": "error", "reason": "invalid_command"})
+        );
+
+        context.rooms.default_room().advance(1, 1.0 / 30.0, 0);
+        let state = next_frame(&mut client).await;
+        assert_eq!(state["type"], "state");
+        assert_eq!(state["payload"]["tick"], 1);
+        assert_eq!(state["payload"]["ship"]["uuid"], uuid.to_string());
+        assert_eq!(state["payload"]["ship"]["engines"]["front"], true);
+
+        drop(client);
+        wait_for_no_ships(&context, &mut ended).await;
+    }
+
+    #[tokio::test]
+    async fn kicked_client_is_told_and_disconnected() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let (route, mut ended) = watched_route(context.clone());
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+
+        assert!(context
+            .connections
+            .send_message(uuid, ServerMessage::Kicked));
+        assert!(context.connections.send(uuid, Directive::Close));
+        assert_eq!(next_frame(&mut client).await["type"], "kicked");
+        wait_for_close(&mut client).await;
+
+        wait_for_no_ships(&context, &mut ended).await;
+        // Unregistered when the connection closed
+        assert!(!context.connections.send(uuid, Directive::Close));
+    }
+
+    #[tokio::test]
+    async fn chat_is_relayed_to_the_other_ships_of_the_room() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let mut client = warp::test::ws()
+                .path("/socket")
+                .handshake(route(context.clone()))
+                .await
+                .unwrap();
+            assert_eq!(next_frame(&mut client).await["type"], "welcome");
+            clients.push(client);
+        }
+
+        clients[0]
+            .send_text(r#"{"data": {"chat": "hello\u0007"}}"#)
+            .await;
+        for client in &mut clients {
+            let chat = next_frame(client).await;
+            assert_eq!(chat["type"], "chat");
+            assert_eq!(chat["text"], "hello");
+            assert!(chat["from"].is_string());
+        }
+
+        let long = "a".repeat(201);
+        clients[0]
+            .send_text(&format!(r#"{{"data": {{"chat": "{}"}}}}"#, long))
+            .await;
+        assert_eq!(
+            next_frame(&mut clients[0]).await,
+            serde_json::json!({"type": "error", "reason": "chat_too_long"})
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_is_heard_only_in_range() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut context = context(shutdown);
+        context.chat_range = Some(50.0);
+        let mut clients = Vec::new();
+        for offset in [0.0, 10.0, 1000.0] {
+            let mut client = warp::test::ws()
+                .path("/socket")
+                .handshake(route(context.clone()))
+                .await
+                .unwrap();
+            let welcome = next_frame(&mut client).await;
+            let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+            let room = context.rooms.default_room();
+            let solar_system = room.solar_system.read();
+            solar_system.ships[&uuid].lock().position = (offset, 500.0, 0.0);
+            clients.push(client);
+        }
+
+        clients[0].send_text(r#"{"data": {"chat": "hello"}}"#).await;
+        for client in &mut clients[..2] {
+            assert_eq!(next_frame(client).await["text"], "hello");
+        }
+        // The far ship gets the next state, without the chat before it
+        context.rooms.default_room().advance(1, 1.0 / 30.0, 0);
+        assert_eq!(next_frame(&mut clients[2]).await["type"], "state");
+    }
+
+    #[tokio::test]
+    async fn client_declaring_another_protocol_version_is_refused() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let mut client = warp::test::ws()
+            .path(&format!(
+                "/socket?protocol_version={}",
+                PROTOCOL_VERSION + 1
+            ))
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_frame(&mut client).await,
+            serde_json::json!({"type": "error", "reason": "unsupported_protocol_version"})
+        );
+        client.recv_closed().await.unwrap();
+
+        let mut client = warp::test::ws()
+            .path(&format!("/socket?protocol_version={}", PROTOCOL_VERSION))
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        assert_eq!(welcome["type"], "welcome");
+        assert_eq!(welcome["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn ships_in_other_rooms_are_not_broadcast() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let mut clients = Vec::new();
+        for room in ["alpha", "beta"] {
+            let mut client = warp::test::ws()
+                .path(&format!("/socket?room={}", room))
+                .handshake(route(context.clone()))
+                .await
+                .unwrap();
+            let welcome = next_frame(&mut client).await;
+            clients.push((client, welcome["uuid"].as_str().unwrap().to_string()));
+        }
+
+        for room in context.rooms.all() {
+            room.advance(1, 1.0 / 30.0, 0);
+        }
+        for (client, uuid) in &mut clients {
+            let state = next_frame(client).await;
+            let ships: Vec<&str> = state["payload"]["ships"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|ship| ship["uuid"].as_str().unwrap())
+                .collect();
+            assert_eq!(ships, [uuid.as_str()]);
+        }
+        assert!(context
+            .rooms
+            .default_room()
+            .solar_system
+            .read()
+            .ships
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn connection_beyond_max_players_is_refused() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut context = context(shutdown);
+        context.rooms = Arc::new(Rooms::new(
+            SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT),
+            Some(2),
+        ));
+        let mut clients = Vec::new();
+        for room in ["alpha", "beta"] {
+            let mut client = warp::test::ws()
+                .path(&format!("/socket?room={}", room))
+                .handshake(route(context.clone()))
+                .await
+                .unwrap();
+            assert_eq!(next_frame(&mut client).await["type"], "welcome");
+            clients.push(client);
+        }
+
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_frame(&mut client).await,
+            serde_json::json!({"type": "error", "reason": "server_full"})
+        );
+        client.recv_closed().await.unwrap();
+        assert!(context
+            .rooms
+            .default_room()
+            .solar_system
+            .read()
+            .ships
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn client_missing_the_pongs_is_dropped() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut context = context(shutdown);
+        // Loose enough for the answered pongs of a loaded machine
+        context.ping_interval = Duration::from_millis(50);
+        context.pong_timeout = Duration::from_secs(1);
+        let (route, mut ended) = watched_route(context.clone());
+        let (address, server) = warp::serve(route.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        // Upgrades, then never reads the pings nor answers them
+        let mut silent = tokio::net::TcpStream::connect(address).await.unwrap();
+        silent
+            .write_all(
+                b"GET /socket HTTP/1.1\r\nHost: localhost\r\nConnection: upgrade\r\n\
+                  Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = [0; 12];
+        silent.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 101");
+
+        // Answers the pings, the warp client does it by itself
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+
+        // The silent one is the first to end
+        connection_ended(&mut ended).await;
+        let room = context.rooms.default_room();
+        let ships: Vec<Uuid> = room.solar_system.read().ships.keys().copied().collect();
+        assert_eq!(ships, [uuid]);
+    }
+
+    #[tokio::test]
+    async fn connection_task_ends_when_the_socket_closes() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let (route, mut ended) = watched_route(context.clone());
+        let room = context.rooms.default_room();
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        next_frame(&mut client).await;
+        assert_eq!(room.snapshots.receiver_count(), 1);
+        assert_eq!(context.metrics.connected_clients(), 1);
+
+        drop(client);
+        // The receiver of the snapshots goes away with the task
+        connection_ended(&mut ended).await;
+        assert_eq!(room.snapshots.receiver_count(), 0);
+        assert!(room.solar_system.read().ships.is_empty());
+        assert_eq!(context.metrics.connected_clients(), 0);
+        room.advance(1, 1.0 / 30.0, 0);
+        assert_eq!(room.snapshots.receiver_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn time_sync_echoes_the_client_time() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        next_frame(&mut client).await;
+        context.rooms.default_room().advance(2, 1.0 / 30.0, 0);
+        assert_eq!(next_frame(&mut client).await["payload"]["tick"], 2);
+
+        client
+            .send_text(r#"{"type": "time_sync", "client_time_ms": 1234}"#)
+            .await;
+        let time_sync = next_frame(&mut client).await;
+        assert_eq!(time_sync["type"], "time_sync");
+        assert_eq!(time_sync["client_time_ms"], 1234);
+        assert_eq!(time_sync["tick"], 2);
+        assert!(time_sync["server_time_ms"].is_u64());
+    }
+
+    /// Until the connection task returned, its ship went with it
+    async fn wait_for_no_ships(context: &SocketContext, ended: &mut mpsc::UnboundedReceiver<()>) {
+        connection_ended(ended).await;
+        assert!(
+            context
+                .rooms
+                .default_room()
+                .solar_system
+                .read()
+                .ships
+                .is_empty(),
+            "the ship outlived its connection"
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnecting_with_the_session_restores_the_ship() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let (route, mut ended) = watched_route(context.clone());
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+        let session = welcome["session"].as_str().unwrap().to_string();
+
+        {
+            let room = context.rooms.default_room();
+            let solar_system = room.solar_system.read();
+            let mut ship = solar_system.ships[&uuid].lock();
+            ship.position = (12.0, -3.0, 40.0);
+            ship.speed = (1.0, 2.0, 3.0);
+        }
+        drop(client);
+        wait_for_no_ships(&context, &mut ended).await;
+
+        let mut client = warp::test::ws()
+            .path(&format!("/socket?session={}", session))
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        assert_eq!(welcome["uuid"], uuid.to_string());
+        assert_eq!(welcome["session"], session);
+
+        let room = context.rooms.default_room();
+        let solar_system = room.solar_system.read();
+        let ship = solar_system.ships[&uuid].lock();
+        assert_eq!(ship.position, (12.0, -3.0, 40.0));
+        assert_eq!(ship.speed, (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn connections_observe_identical_snapshots_per_tick() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        for _ in 0..2 {
+            solar_system.add_ship(Arc::new(parking_lot::Mutex::new(TheShip::new_with_uuid(
+                Uuid::new_v4(),
+            ))));
+        }
+        let (sender, _) = broadcast::channel(16);
+        let mut first = sender.subscribe();
+        let mut second = sender.subscribe();
+
+        for _ in 0..10 {
+            solar_system.advance(1.0 / 30.0);
+            sender.send(Arc::new(solar_system.snapshot(0))).unwrap();
+        }
+
+        // What a connection sends for each tick, without its own ship
+        let frames = |receiver: &mut broadcast::Receiver<Arc<Snapshot>>| {
+            let mut frames = Vec::new();
+            while let Ok(snapshot) = receiver.try_recv() {
+                let payload = StatePayload::new(&snapshot, serde_json::Value::Null);
+                frames.push(serde_json::to_string(&payload).unwrap());
+            }
+            frames
+        };
+        let first = frames(&mut first);
+        assert_eq!(first.len(), 10);
+        assert_eq!(first, frames(&mut second));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn frames_hold_planets_and_ships_of_the_same_tick() {
+        const TICKS: u64 = 50;
+        let (_shutdown, shutdown) = watch::channel(false);
+        let context = context(shutdown);
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        let welcome = next_frame(&mut client).await;
+        let uuid: Uuid = welcome["uuid"].as_str().unwrap().parse().unwrap();
+
+        // Moves the ship between the ticks, as the commands of its client would
+        let mover = {
+            let context = context.clone();
+            tokio::spawn(async move {
+                for _ in 0..1000 {
+                    {
+                        let room = context.rooms.default_room();
+                        let solar_system = room.solar_system.read();
+                        let mut ship = solar_system.ships[&uuid].lock();
+                        ship.position.0 += 1.0;
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let simulation = {
+            let context = context.clone();
+            tokio::spawn(async move {
+                let mut expected = std::collections::HashMap::new();
+                for _ in 0..TICKS {
+                    let snapshot = context.rooms.default_room().advance(1, 1.0 / 30.0, 0);
+                    let ship = snapshot.ships.iter().find(|ship| ship.uuid == uuid);
+                    let payload = StatePayload::new(&snapshot, ship.unwrap().to_json());
+                    expected.insert(snapshot.tick, serde_json::to_string(&payload).unwrap());
+                    tokio::task::yield_now().await;
+                }
+                expected
+            })
+        };
+        let expected = simulation.await.unwrap();
+        mover.await.unwrap();
+
+        let mut frames = 0;
+        loop {
+            let state = next_frame(&mut client).await;
+            let tick = state["payload"]["tick"].as_u64().unwrap();
+            let payload: serde_json::Value = serde_json::from_str(&expected[&tick]).unwrap();
+            assert_eq!(state["payload"], payload, "frame of tick {}", tick);
+            frames += 1;
+            if tick == TICKS {
+                break;
+            }
+        }
+        assert!(frames > 1);
+    }
+
+    #[tokio::test]
+    async fn paused_room_keeps_sending_frames() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut context = context(shutdown);
+        context.broadcast_stride = 3;
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut client).await["type"], "welcome");
+
+        let room = context.rooms.default_room();
+        room.advance(1, 1.0 / 30.0, 0);
+        let state = next_frame(&mut client).await;
+        assert_eq!(state["payload"]["tick"], 1);
+        assert_eq!(state["payload"]["paused"], false);
+
+        // Paused on a tick which is not a multiple of the stride
+        room.solar_system.write().set_paused(true);
+        for _ in 0..6 {
+            room.advance(1, 1.0 / 30.0, 0);
+        }
+        for _ in 0..2 {
+            let state = next_frame(&mut client).await;
+            assert_eq!(state["payload"]["tick"], 1);
+            assert_eq!(state["payload"]["paused"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_is_sent_without_the_ship_of_the_client() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut context = context(shutdown);
+        context.replaying = true;
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context.clone()))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut client).await["type"], "welcome");
+
+        // A recorded world, the ship of the client is not in it
+        let room = context.rooms.default_room();
+        let mut snapshot = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).snapshot(0);
+        snapshot.tick = 42;
+        snapshot.ships = vec![TheShip::new_with_uuid(Uuid::new_v4())];
+        room.publish(snapshot);
+
+        let state = next_frame(&mut client).await;
+        assert_eq!(state["type"], "state");
+        assert_eq!(state["payload"]["tick"], 42);
+        assert!(state["payload"]["ship"].is_null());
+        assert_eq!(state["payload"]["ships"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn subprotocol_enables_its_features() {
+        let mut options = SocketOptions::default();
+        let selected = options.negotiate("owweb.v1.binary+delta");
+        assert_eq!(selected.as_deref(), Some("owweb.v1.binary+delta"));
+        assert_eq!(options.encoding, Encoding::MessagePack);
+        assert!(options.delta);
+        assert!(!options.compress);
+        assert_eq!(options.protocol_version, Some(PROTOCOL_VERSION));
+
+        let mut options = SocketOptions::default();
+        assert_eq!(options.negotiate("owweb.v1").as_deref(), Some("owweb.v1"));
+        assert_eq!(options.encoding, Encoding::Json);
+        assert!(!options.delta);
+    }
+
+    #[test]
+    fn first_supported_subprotocol_is_selected() {
+        let mut options = SocketOptions::default();
+        let selected = options.negotiate("owweb.v2.delta, owweb.v1.teleport, owweb.v1.compress");
+        assert_eq!(selected.as_deref(), Some("owweb.v1.compress"));
+        assert!(options.compress);
+        assert!(!options.delta);
+
+        let mut options = SocketOptions::default();
+        assert_eq!(options.negotiate("chat, owweb.v1.binary+"), None);
+        assert_eq!(options.encoding, Encoding::Json);
+    }
+
+    #[tokio::test]
+    async fn invalid_json_gets_an_error_and_keeps_the_socket_open() {
+        let (_shutdown, shutdown) = watch::channel(false);
+        let mut client = warp::test::ws()
+            .path("/socket")
+            .handshake(route(context(shutdown)))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut client).await["type"], "welcome");
+
+        client.send_text("{\"data\": {\"front\": tru").await;
+        let error = next_frame(&mut client).await;
+        assert_eq!(error["type"], "error");
+        assert_eq!(error["reason"], "invalid_json");
+
+        // Still served
+        client
+            .send_text(r#"{"type":"time_sync","client_time_ms":1}"#)
+            .await;
+        assert_eq!(next_frame(&mut client).await["type"], "time_sync");
+    }
+
+    #[tokio::test]
+    async fn socket_and_api_share_one_port() {
+        let (_shutdown, shutdown_receiver) = watch::channel(false);
+        let api = crate::api_urls::route(
+            Some("https://api.example.com".to_string()),
+            "ws://127.0.0.1:3030/socket".to_string(),
+        );
+        // As served with `SINGLE_PORT`
+        let routes = warp::path("api")
+            .and(api)
+            .or(route(context(shutdown_receiver)));
+        let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/socket", address))
+            .await
+            .unwrap();
+        let welcome = socket.next().await.unwrap().unwrap();
+        let welcome: serde_json::Value = serde_json::from_str(welcome.to_text().unwrap()).unwrap();
+        assert_eq!(welcome["type"], "welcome");
+
+        let mut http = tokio::net::TcpStream::connect(address).await.unwrap();
+        http.write_all(
+            b"GET /api/auth-api-url HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains(r#""backend_url":"https://api.example.com""#));
+    }
+}
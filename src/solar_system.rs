@@ -0,0 +1,1506 @@
+use crate::probe::Probe;
+use crate::ship::{
+    Attractor, OrbitedBody, ShipSettings, SurfaceCollision, TheShip, Throttle, WorldBoundary,
+    DEFAULT_GRAVITY_SOFTENING, SPAWN_POINT,
+};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Name of the sun in the orbits of the ships
+const SUN: &str = "Sun";
+/// Mass of the sun at the origin, the main attractor of the ships
+pub const DEFAULT_SUN_MASS: f64 = 10000.0;
+/// Distance to the sun of the edge of the playable world
+pub const DEFAULT_WORLD_RADIUS: f64 = 1000.0;
+pub const DEFAULT_GRAVITATIONAL_CONSTANT: f64 = 1.0;
+const KEPLER_ITERATIONS: usize = 6;
+/// Ships closer than this to a planet or a moon slowly refuel
+const REFUEL_DISTANCE: f64 = 15.0;
+/// A ship coasting closer than this to the surface of a planet, slow enough, lands on it
+const LANDING_ALTITUDE: f64 = 2.0;
+const LANDING_MAX_RELATIVE_SPEED: f64 = 2.0;
+/// Distance between two spawn slots, so that two ships spawned side by side do not collide
+const SPAWN_SPACING: f64 = 3.0 * TheShip::COLLISION_RADIUS;
+/// Rings of slots around the spawn point, 6 more slots on each ring
+const SPAWN_RINGS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    Planet,
+    Moon,
+}
+
+impl BodyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BodyKind::Planet => "planet",
+            BodyKind::Moon => "moon",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "planet" => Some(BodyKind::Planet),
+            "moon" => Some(BodyKind::Moon),
+            _ => None,
+        }
+    }
+}
+
+/// How the planets and the moons move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanetMotion {
+    /// On their fixed Kepler orbits, whatever the gravity
+    Rails,
+    /// Under the gravity of the sun and of each other, from where the rails put them
+    NBody,
+}
+
+impl PlanetMotion {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rails" => Some(PlanetMotion::Rails),
+            "n_body" => Some(PlanetMotion::NBody),
+            _ => None,
+        }
+    }
+}
+
+/// World-space state of a body moving under gravity
+#[derive(Debug, Clone, Copy)]
+struct BodyState {
+    position: (f64, f64, f64),
+    velocity: (f64, f64, f64),
+}
+
+/// World-space position of a planet or a moon
+#[derive(Debug, Clone)]
+pub struct BodyPosition {
+    pub name: String,
+    pub kind: BodyKind,
+    pub position: (f64, f64, f64),
+}
+
+/// Immutable copy of the world at the end of a tick, shared with every reader
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub server_time_ms: u64, // when the tick ended, in milliseconds since the server started
+    pub positions: Vec<BodyPosition>,
+    pub ships: Vec<TheShip>,
+    pub probes: Vec<Probe>,
+    pub world_scale: f64,
+    pub paused: bool,
+    pub frame: u64, // snapshots the room published before this one, counts on while paused
+}
+
+/// Latest snapshot, replaced by the simulation loop after each tick
+pub type SharedSnapshot = Arc<RwLock<Arc<Snapshot>>>;
+
+/// Where the uuids of the new ships come from
+#[derive(Clone)]
+enum UuidSource {
+    Random,
+    /// splitmix64, enough to get the same uuids from the same seed
+    Seeded {
+        state: u64,
+    },
+}
+
+impl UuidSource {
+    fn next(&mut self) -> Uuid {
+        match self {
+            UuidSource::Random => Uuid::new_v4(),
+            UuidSource::Seeded { state } => {
+                let mut next_u64 = || {
+                    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = *state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^ (z >> 31)
+                };
+                let bits = (next_u64() as u128) << 64 | next_u64() as u128;
+                uuid::Builder::from_u128(bits)
+                    .set_variant(uuid::Variant::RFC4122)
+                    .set_version(uuid::Version::Random)
+                    .build()
+            }
+        }
+    }
+}
+
+/// Motion of a planet or a moon during the last tick
+struct BodyMotion {
+    name: String,
+    position: (f64, f64, f64),
+    radius: f64,
+    mass: f64,
+    softening: f64,
+    displacement: (f64, f64, f64),
+    velocity: (f64, f64, f64),
+}
+
+impl BodyMotion {
+    fn attractor(&self) -> Attractor {
+        Attractor {
+            position: self.position,
+            mass: self.mass,
+            softening: self.softening,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Planet {
+    pub name: String,
+    semi_major_axis: f64,
+    eccentricity: f64,
+    inclination: f64, // radians, tilt of the orbital plane around the x axis
+    mean_anomaly: f64,
+    angular_velocity: f64, // mean motion, radians per second
+    mass: f64,
+    radius: f64,              // of the surface, the ships cannot go below it
+    moons: Vec<Planet>,       // orbiting this planet instead of the sun
+    state: Option<BodyState>, // replaces the orbit in `PlanetMotion::NBody`, once seeded
+}
+
+impl Planet {
+    pub fn new(
+        name: &str,
+        semi_major_axis: f64,
+        eccentricity: f64,
+        inclination_degrees: f64,
+        orbital_period: f64,
+        mass: f64,
+        radius: f64,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            semi_major_axis,
+            eccentricity,
+            inclination: inclination_degrees.to_radians(),
+            mean_anomaly: 0.0,
+            angular_velocity: 2.0 * PI / orbital_period,
+            mass,
+            radius,
+            moons: Vec::new(),
+            state: None,
+        }
+    }
+
+    pub fn with_moons(mut self, moons: Vec<Planet>) -> Self {
+        self.moons = moons;
+        self
+    }
+
+    /// Same body with its orbit, its radius and its moons `factor` times larger
+    fn scaled(mut self, factor: f64) -> Self {
+        self.semi_major_axis *= factor;
+        self.radius *= factor;
+        self.moons = self
+            .moons
+            .into_iter()
+            .map(|moon| moon.scaled(factor))
+            .collect();
+        self
+    }
+
+    /// This body and its moons
+    fn names(&self) -> Vec<&str> {
+        std::iter::once(self.name.as_str())
+            .chain(self.moons.iter().flat_map(Planet::names))
+            .collect()
+    }
+
+    /// Take the body `name` and its moons out of `bodies`, searching the moons too
+    fn remove_from(bodies: &mut Vec<Planet>, name: &str) -> bool {
+        let count = bodies.len();
+        bodies.retain(|body| body.name != name);
+        count != bodies.len()
+            || bodies
+                .iter_mut()
+                .any(|body| Planet::remove_from(&mut body.moons, name))
+    }
+
+    pub fn update_position(&mut self, delta_time: f64) {
+        self.mean_anomaly += self.angular_velocity * delta_time;
+        if self.mean_anomaly > 2.0 * PI {
+            self.mean_anomaly -= 2.0 * PI;
+        }
+
+        for moon in &mut self.moons {
+            moon.update_position(delta_time);
+        }
+    }
+
+    /// Solve Kepler's equation `M = E - e * sin(E)` with Newton's method
+    fn eccentric_anomaly(&self) -> f64 {
+        let e = self.eccentricity;
+        let mut eccentric_anomaly = if e < 0.8 { self.mean_anomaly } else { PI };
+
+        for _ in 0..KEPLER_ITERATIONS {
+            let residual = eccentric_anomaly - e * eccentric_anomaly.sin() - self.mean_anomaly;
+            eccentric_anomaly -= residual / (1.0 - e * eccentric_anomaly.cos());
+        }
+
+        eccentric_anomaly
+    }
+
+    /// Position on the ellipse relative to the body it orbits, which sits at a focus
+    /// The perihelion is on the x axis, then the orbital plane is tilted by the inclination
+    pub fn position(&self) -> (f64, f64, f64) {
+        let eccentric_anomaly = self.eccentric_anomaly();
+        let semi_minor_axis = self.semi_major_axis * (1.0 - self.eccentricity.powi(2)).sqrt();
+
+        let x = self.semi_major_axis * (eccentric_anomaly.cos() - self.eccentricity);
+        let y = semi_minor_axis * eccentric_anomaly.sin();
+
+        (x, y * self.inclination.cos(), y * self.inclination.sin())
+    }
+
+    /// Direction of the motion on the ellipse, as `position`
+    fn direction_of_motion(&self) -> (f64, f64, f64) {
+        let eccentric_anomaly = self.eccentric_anomaly();
+        let semi_minor_axis = self.semi_major_axis * (1.0 - self.eccentricity.powi(2)).sqrt();
+
+        // Derivative of the position along the eccentric anomaly
+        let x = -self.semi_major_axis * eccentric_anomaly.sin();
+        let y = semi_minor_axis * eccentric_anomaly.cos();
+        let norm = (x.powi(2) + y.powi(2)).sqrt();
+
+        (
+            x / norm,
+            y * self.inclination.cos() / norm,
+            y * self.inclination.sin() / norm,
+        )
+    }
+
+    /// Where the orbit puts the body, with the speed of the same ellipse under the gravity
+    /// of `parent`
+    fn seeded_state(
+        &self,
+        parent: BodyState,
+        parent_mass: f64,
+        gravitational_constant: f64,
+        softening: f64,
+    ) -> BodyState {
+        let (x, y, z) = self.position();
+        let position = (
+            parent.position.0 + x,
+            parent.position.1 + y,
+            parent.position.2 + z,
+        );
+        let pull = Attractor {
+            position: parent.position,
+            mass: parent_mass,
+            softening,
+        }
+        .pull(position, gravitational_constant);
+        let pull = (pull.0.powi(2) + pull.1.powi(2) + pull.2.powi(2)).sqrt();
+        let distance = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
+        // Vis-viva, v² = GM (2/r - 1/a)
+        let speed = (pull * distance * (2.0 - distance / self.semi_major_axis))
+            .max(0.0)
+            .sqrt();
+
+        let direction = self.direction_of_motion();
+        BodyState {
+            position,
+            velocity: (
+                parent.velocity.0 + direction.0 * speed,
+                parent.velocity.1 + direction.1 * speed,
+                parent.velocity.2 + direction.2 * speed,
+            ),
+        }
+    }
+
+    /// Seed the bodies without a state, then their moons from them
+    fn seed_states(
+        planets: &mut [Planet],
+        parent: BodyState,
+        parent_mass: f64,
+        gravitational_constant: f64,
+        softening: f64,
+    ) {
+        for planet in planets {
+            let state = match planet.state {
+                Some(state) => state,
+                None => planet.seeded_state(parent, parent_mass, gravitational_constant, softening),
+            };
+            planet.state = Some(state);
+            Planet::seed_states(
+                &mut planet.moons,
+                state,
+                planet.mass,
+                gravitational_constant,
+                softening,
+            );
+        }
+    }
+
+    /// The states of this body and of its moons, with their masses
+    fn collect_states<'a>(planets: &'a mut [Planet], states: &mut Vec<(&'a mut BodyState, f64)>) {
+        for planet in planets {
+            let Planet {
+                state, moons, mass, ..
+            } = planet;
+            if let Some(state) = state {
+                states.push((state, *mass));
+            }
+            Planet::collect_states(moons, states);
+        }
+    }
+
+    /// Push this body and its moons with their positions resolved from `origin`
+    fn collect_world_positions<'a>(
+        &'a self,
+        kind: BodyKind,
+        origin: (f64, f64, f64),
+        bodies: &mut Vec<(&'a Planet, BodyKind, (f64, f64, f64))>,
+    ) {
+        let position = match &self.state {
+            Some(state) => state.position,
+            None => {
+                let (x, y, z) = self.position();
+                (origin.0 + x, origin.1 + y, origin.2 + z)
+            }
+        };
+        bodies.push((self, kind, position));
+
+        for moon in &self.moons {
+            moon.collect_world_positions(BodyKind::Moon, position, bodies);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SolarSystem {
+    planets: Vec<Planet>,
+    pub ships: HashMap<Uuid, Arc<Mutex<TheShip>>>,
+    probes: Vec<Probe>,
+    gravitational_constant: f64,
+    gravity_softening: f64,
+    sun_mass: f64,
+    planet_motion: PlanetMotion,
+    tick: u64, // steps run by `advance` since the creation
+    ship_uuids: UuidSource,
+    surface_collision: SurfaceCollision,
+    world_radius: f64,
+    world_boundary: WorldBoundary,
+    ship_settings: ShipSettings,
+    world_scale: f64, // factor of every length, given to the clients with the state
+    paused: bool,     // `advance` leaves everything where it is
+    step_requested: bool, // one step to run while paused, by the next `advance`
+}
+
+impl SolarSystem {
+    pub fn new(gravitational_constant: f64) -> Self {
+        Self {
+            planets: vec![
+                Planet::new("Mercury", 50.0, 0.206, 7.0, 0.24 * 60.0, 20.0, 2.0),
+                Planet::new("Venus", 70.0, 0.007, 3.4, 0.62 * 60.0, 150.0, 3.5),
+                Planet::new("Earth", 90.0, 0.017, 0.0, 1.0 * 60.0, 200.0, 4.0).with_moons(vec![
+                    Planet::new("Moon", 12.0, 0.055, 5.1, 0.075 * 60.0, 5.0, 1.5),
+                ]),
+                Planet::new("Mars", 110.0, 0.093, 1.85, 1.88 * 60.0, 50.0, 2.5),
+                Planet::new("Jupiter", 150.0, 0.049, 1.3, 11.86 * 60.0, 1500.0, 8.0),
+            ],
+            ships: HashMap::new(),
+            probes: Vec::new(),
+            gravitational_constant,
+            gravity_softening: DEFAULT_GRAVITY_SOFTENING,
+            sun_mass: DEFAULT_SUN_MASS,
+            planet_motion: PlanetMotion::Rails,
+            tick: 0,
+            ship_uuids: UuidSource::Random,
+            surface_collision: SurfaceCollision::Stop,
+            world_radius: DEFAULT_WORLD_RADIUS,
+            world_boundary: WorldBoundary::Repel,
+            ship_settings: ShipSettings::default(),
+            world_scale: 1.0,
+            paused: false,
+            step_requested: false,
+        }
+    }
+
+    pub fn with_sun_mass(mut self, sun_mass: f64) -> Self {
+        self.sun_mass = sun_mass;
+        self
+    }
+
+    pub fn with_gravity_softening(mut self, gravity_softening: f64) -> Self {
+        self.gravity_softening = gravity_softening;
+        self
+    }
+
+    pub fn with_planet_motion(mut self, planet_motion: PlanetMotion) -> Self {
+        self.planet_motion = planet_motion;
+        self
+    }
+
+    pub fn with_ship_settings(mut self, ship_settings: ShipSettings) -> Self {
+        self.ship_settings = ship_settings;
+        self
+    }
+
+    pub fn with_world_boundary(mut self, radius: f64, boundary: WorldBoundary) -> Self {
+        self.world_radius = radius * self.world_scale;
+        self.world_boundary = boundary;
+        self
+    }
+
+    pub fn with_surface_collision(mut self, surface_collision: SurfaceCollision) -> Self {
+        self.surface_collision = surface_collision;
+        self
+    }
+
+    /// Replace the default planets, e.g. by the ones of `PLANETS_CONFIG`
+    pub fn with_planets(mut self, planets: Vec<Planet>) -> Self {
+        let world_scale = self.world_scale;
+        self.planets = planets
+            .into_iter()
+            .map(|planet| planet.scaled(world_scale))
+            .collect();
+        self
+    }
+
+    /// Multiply the orbits and the radii of the planets and moons, the edge of the world
+    /// and the spawn point by `world_scale`, so that the geometry keeps its proportions.
+    /// The planets and the world radius given before or after are scaled alike
+    pub fn with_world_scale(mut self, world_scale: f64) -> Self {
+        let factor = world_scale / self.world_scale;
+        self.planets = self
+            .planets
+            .into_iter()
+            .map(|planet| planet.scaled(factor))
+            .collect();
+        self.world_radius *= factor;
+        self.world_scale = world_scale;
+        self
+    }
+
+    /// Give the ships uuids drawn from `seed` instead of random ones. The physics has
+    /// no randomness, a seeded world advanced by the same steps always ends up the same
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.ship_uuids = UuidSource::Seeded { state: seed };
+        self
+    }
+
+    /// A ship at the free slot nearest to the spawn point, with the next uuid of the world.
+    /// It is not added yet
+    pub fn new_ship(&mut self) -> TheShip {
+        let mut ship = TheShip::new_with_uuid(self.ship_uuids.next());
+        ship.spool_time = self.ship_settings.spool_time;
+        ship.max_speed = self.ship_settings.max_speed;
+        ship.integrator = self.ship_settings.integrator;
+        ship.trail_length = self.ship_settings.trail_length;
+        ship.spawn_point = (
+            SPAWN_POINT.0 * self.world_scale,
+            SPAWN_POINT.1 * self.world_scale,
+            SPAWN_POINT.2 * self.world_scale,
+        );
+        ship.position = ship.spawn_point;
+        ship.prev_position = ship.spawn_point;
+
+        let occupied: Vec<(f64, f64, f64)> = self
+            .ships
+            .values()
+            .map(|other| other.lock().position)
+            .collect();
+        // Every slot taken, the ship spawns at the spawn point anyway
+        if let Some(slot) = spawn_slots(ship.position).find(|&slot| {
+            occupied
+                .iter()
+                .all(|&position| distance(position, slot) >= 2.0 * TheShip::COLLISION_RADIUS)
+        }) {
+            ship.position = slot;
+            ship.prev_position = slot;
+        }
+        ship
+    }
+
+    /// Run one step of the simulation, lasting `delta_time` seconds
+    pub fn advance(&mut self, delta_time: f64) {
+        if self.paused && !std::mem::take(&mut self.step_requested) {
+            return;
+        }
+        self.update(delta_time);
+        self.tick += 1;
+    }
+
+    /// Freeze the planets, the ships and the probes, the tick stops counting too
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.step_requested = false;
+    }
+
+    /// Let the next `advance` of the paused world run one step, the following ones are
+    /// skipped again. False when the world is not paused
+    pub fn request_step(&mut self) -> bool {
+        self.step_requested = self.paused;
+        self.paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn update(&mut self, delta_time: f64) {
+        let previous_positions = self.positions();
+        self.move_planets(delta_time);
+
+        let attractors = self.attractors();
+        let bodies = self.body_motions(&previous_positions, delta_time);
+
+        for probe in &mut self.probes {
+            probe.update(delta_time, &attractors, self.gravitational_constant);
+        }
+        let world_radius = self.world_radius;
+        self.probes.retain(|probe| {
+            !probe.is_expired()
+                && distance(probe.position, (0.0, 0.0, 0.0)) < world_radius
+                && bodies
+                    .iter()
+                    .all(|body| distance(probe.position, body.position) > body.radius)
+        });
+
+        let mut launched = Vec::new();
+        for ship in self.ships.values_mut() {
+            let mut ship = ship.lock();
+            // Before anything moves it: thrust, gravity, landing, orbit or collisions
+            ship.prev_position = ship.position;
+            if ship.is_destroyed() {
+                ship.wreck(delta_time);
+                ship.probe_requested = false;
+                continue;
+            }
+
+            let inserted = ship.orbit_request.take().and_then(|(target, radius)| {
+                let body = bodies.iter().find(|body| body.name == target)?;
+                ship.insert_into_orbit(
+                    &target,
+                    radius,
+                    body.attractor(),
+                    body.velocity,
+                    self.gravitational_constant,
+                );
+                Some(())
+            });
+            let orbited = ship
+                .orbit
+                .as_ref()
+                .and_then(|orbit| bodies.iter().find(|body| body.name == orbit.target));
+
+            if inserted.is_some() {
+                // Placed where it is at the end of this step already
+            } else if let Some(body) = orbited.filter(|_| !ship.is_thrusting()) {
+                // Captured by the body until an engine fires
+                ship.coast_in_orbit(
+                    delta_time,
+                    body.attractor(),
+                    body.displacement,
+                    body.velocity,
+                    self.gravitational_constant,
+                );
+            } else {
+                ship.orbit = None;
+                let landed_on = ship
+                    .landed_on
+                    .as_ref()
+                    .and_then(|name| bodies.iter().find(|body| &body.name == name));
+
+                match landed_on {
+                    Some(body) if !ship.is_thrusting() => {
+                        ship.ride(delta_time, body.displacement);
+                    }
+                    _ => {
+                        if let Some(body) = landed_on {
+                            ship.take_off(body.velocity);
+                        }
+
+                        let autopilot_target = ship
+                            .autopilot
+                            .as_ref()
+                            .and_then(|name| bodies.iter().find(|body| &body.name == name));
+                        match autopilot_target {
+                            Some(body) => ship.match_velocity(body.velocity, delta_time),
+                            None => ship.autopilot = None,
+                        }
+
+                        ship.update(delta_time, &attractors, self.gravitational_constant);
+                        for body in &bodies {
+                            ship.collide_with_surface(
+                                body.position,
+                                body.radius,
+                                body.velocity,
+                                self.surface_collision,
+                            );
+                        }
+                        ship.keep_within(self.world_radius, self.world_boundary, delta_time);
+
+                        if !ship.is_thrusting() {
+                            let landing_site = bodies.iter().find(|body| {
+                                ship.distance_to(body.position) < body.radius + LANDING_ALTITUDE
+                                    && ship.speed_relative_to(body.velocity)
+                                        < LANDING_MAX_RELATIVE_SPEED
+                            });
+                            if let Some(body) = landing_site {
+                                ship.land_on(&body.name);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if bodies
+                .iter()
+                .any(|body| ship.distance_to(body.position) < REFUEL_DISTANCE)
+            {
+                ship.refuel(delta_time);
+            }
+
+            if std::mem::take(&mut ship.probe_requested) {
+                launched.push(Probe::launch(&ship));
+            }
+        }
+
+        for probe in launched {
+            self.probes.retain(|previous| previous.owner != probe.owner);
+            self.probes.push(probe);
+        }
+        self.resolve_ship_collisions();
+
+        let sun = OrbitedBody {
+            name: SUN,
+            attractor: attractors[0], // always first
+            velocity: (0.0, 0.0, 0.0),
+        };
+        let orbited: Vec<OrbitedBody> = std::iter::once(sun)
+            .chain(bodies.iter().map(|body| OrbitedBody {
+                name: &body.name,
+                attractor: body.attractor(),
+                velocity: body.velocity,
+            }))
+            .collect();
+        // Where the ships ended the step, pushed apart by the collisions
+        for ship in self.ships.values() {
+            let mut ship = ship.lock();
+            ship.record_trail();
+            ship.read_orbit(&orbited, self.gravitational_constant);
+        }
+    }
+
+    /// Where `ship` drifts during the next `steps` steps of `step` seconds, engines off.
+    /// The planets move on as in the simulation, the polyline stops on the first surface hit.
+    /// Run on copies of the world and of the ship, taken without holding their locks
+    pub fn predict(self, mut ship: TheShip, steps: u32, step: f64) -> Vec<(f64, f64, f64)> {
+        let mut world = self;
+        ship.release_controls();
+        ship.throttle = Throttle::default();
+        ship.autopilot = None;
+
+        let mut points = Vec::with_capacity(steps as usize);
+        for _ in 0..steps {
+            world.move_planets(step);
+            ship.update(step, &world.attractors(), world.gravitational_constant);
+            points.push(ship.position);
+
+            if world
+                .bodies()
+                .iter()
+                .any(|(body, _, position)| ship.distance_to(*position) < body.radius)
+            {
+                break;
+            }
+        }
+        points
+    }
+
+    fn move_planets(&mut self, delta_time: f64) {
+        match self.planet_motion {
+            PlanetMotion::Rails => {
+                for planet in &mut self.planets {
+                    planet.update_position(delta_time);
+                }
+            }
+            PlanetMotion::NBody => self.integrate_planets(delta_time),
+        }
+    }
+
+    /// One leapfrog step (kick, drift, kick) of every planet and moon. Symplectic, the orbits
+    /// keep their energy instead of spiraling. The sun stays at the origin
+    fn integrate_planets(&mut self, delta_time: f64) {
+        let sun = BodyState {
+            position: (0.0, 0.0, 0.0),
+            velocity: (0.0, 0.0, 0.0),
+        };
+        // The planets added since the last step
+        Planet::seed_states(
+            &mut self.planets,
+            sun,
+            self.sun_mass,
+            self.gravitational_constant,
+            self.gravity_softening,
+        );
+
+        let mut states = Vec::new();
+        Planet::collect_states(&mut self.planets, &mut states);
+        let sun_mass = self.sun_mass;
+        let gravitational_constant = self.gravitational_constant;
+        let softening = self.gravity_softening;
+        let accelerations = |states: &[(&mut BodyState, f64)]| -> Vec<(f64, f64, f64)> {
+            let attractors: Vec<Attractor> = std::iter::once(Attractor {
+                position: sun.position,
+                mass: sun_mass,
+                softening,
+            })
+            .chain(states.iter().map(|(state, mass)| Attractor {
+                position: state.position,
+                mass: *mass,
+                softening,
+            }))
+            .collect();
+
+            states
+                .iter()
+                .enumerate()
+                .map(|(index, (state, _))| {
+                    // Index 0 is the sun, a body does not attract itself
+                    attractors
+                        .iter()
+                        .enumerate()
+                        .filter(|&(other, _)| other != index + 1)
+                        .fold((0.0, 0.0, 0.0), |total, (_, attractor)| {
+                            let pull = attractor.pull(state.position, gravitational_constant);
+                            (total.0 + pull.0, total.1 + pull.1, total.2 + pull.2)
+                        })
+                })
+                .collect()
+        };
+        let kick = |states: &mut [(&mut BodyState, f64)], accelerations: &[(f64, f64, f64)]| {
+            for ((state, _), acceleration) in states.iter_mut().zip(accelerations) {
+                state.velocity.0 += acceleration.0 * delta_time / 2.0;
+                state.velocity.1 += acceleration.1 * delta_time / 2.0;
+                state.velocity.2 += acceleration.2 * delta_time / 2.0;
+            }
+        };
+
+        let before = accelerations(&states);
+        kick(&mut states, &before);
+        for (state, _) in states.iter_mut() {
+            state.position.0 += state.velocity.0 * delta_time;
+            state.position.1 += state.velocity.1 * delta_time;
+            state.position.2 += state.velocity.2 * delta_time;
+        }
+        let after = accelerations(&states);
+        kick(&mut states, &after);
+    }
+
+    /// Bounce every pair of overlapping ships off each other
+    fn resolve_ship_collisions(&self) {
+        // Ships are always locked in uuid order, so two of them are never taken in opposite orders
+        let mut uuids: Vec<&Uuid> = self.ships.keys().collect();
+        uuids.sort();
+
+        for (index, first) in uuids.iter().enumerate() {
+            for second in &uuids[index + 1..] {
+                let mut first_ship = self.ships[*first].lock();
+                let mut second_ship = self.ships[*second].lock();
+                first_ship.collide_with(&mut second_ship);
+            }
+        }
+    }
+
+    /// Where each planet and moon is now, and how it moved since `previous_positions`
+    fn body_motions(
+        &self,
+        previous_positions: &[BodyPosition],
+        delta_time: f64,
+    ) -> Vec<BodyMotion> {
+        self.bodies()
+            .into_iter()
+            .zip(previous_positions)
+            .map(|((body, _, position), previous)| {
+                let displacement = (
+                    position.0 - previous.position.0,
+                    position.1 - previous.position.1,
+                    position.2 - previous.position.2,
+                );
+                let velocity = if delta_time > 0.0 {
+                    (
+                        displacement.0 / delta_time,
+                        displacement.1 / delta_time,
+                        displacement.2 / delta_time,
+                    )
+                } else {
+                    (0.0, 0.0, 0.0)
+                };
+
+                BodyMotion {
+                    name: body.name.clone(),
+                    position,
+                    radius: body.radius,
+                    mass: body.mass,
+                    softening: self.gravity_softening,
+                    displacement,
+                    velocity,
+                }
+            })
+            .collect()
+    }
+
+    /// Every planet and moon with its world-space position
+    fn bodies(&self) -> Vec<(&Planet, BodyKind, (f64, f64, f64))> {
+        let mut bodies = Vec::new();
+        for planet in &self.planets {
+            planet.collect_world_positions(BodyKind::Planet, (0.0, 0.0, 0.0), &mut bodies);
+        }
+        bodies
+    }
+
+    /// The sun (at the origin), the planets and the moons at their current positions
+    fn attractors(&self) -> Vec<Attractor> {
+        let sun = Attractor {
+            position: (0.0, 0.0, 0.0),
+            mass: self.sun_mass,
+            softening: self.gravity_softening,
+        };
+
+        std::iter::once(sun)
+            .chain(
+                self.bodies()
+                    .into_iter()
+                    .map(|(body, _, position)| Attractor {
+                        position,
+                        mass: body.mass,
+                        softening: self.gravity_softening,
+                    }),
+            )
+            .collect()
+    }
+
+    pub fn add_ship(&mut self, ship: Arc<Mutex<TheShip>>) {
+        let uuid = ship.lock().uuid;
+        self.ships.insert(uuid, ship);
+    }
+
+    pub fn remove_ship(&mut self, uuid: Uuid) -> Option<Arc<Mutex<TheShip>>> {
+        self.probes.retain(|probe| probe.owner != uuid);
+        self.ships.remove(&uuid)
+    }
+
+    /// True when a planet or a moon has this name
+    pub fn has_body(&self, name: &str) -> bool {
+        self.bodies().iter().any(|(body, _, _)| body.name == name)
+    }
+
+    /// Add a planet (with its moons) to the running world, it starts at its perihelion.
+    /// Refused when one of the names is already taken
+    pub fn add_planet(&mut self, planet: Planet) -> Result<(), &'static str> {
+        if planet.names().iter().any(|name| self.has_body(name)) {
+            return Err("duplicate_name");
+        }
+        self.planets.push(planet.scaled(self.world_scale));
+        Ok(())
+    }
+
+    /// Remove a planet (with its moons) or a moon, false when there is none with this name
+    pub fn remove_planet(&mut self, name: &str) -> bool {
+        if !Planet::remove_from(&mut self.planets, name) {
+            return false;
+        }
+
+        // The ships landed on it are left floating where it was
+        let names: Vec<String> = self.positions().into_iter().map(|body| body.name).collect();
+        for ship in self.ships.values() {
+            let mut ship = ship.lock();
+            if ship
+                .landed_on
+                .as_ref()
+                .is_some_and(|landed_on| !names.contains(landed_on))
+            {
+                ship.landed_on = None;
+            }
+        }
+        true
+    }
+
+    /// Radius of the planet or the moon with this name
+    pub fn body_radius(&self, name: &str) -> Option<f64> {
+        self.bodies()
+            .iter()
+            .find(|(body, _, _)| body.name == name)
+            .map(|(body, _, _)| body.radius)
+    }
+
+    pub fn snapshot(&self, server_time_ms: u64) -> Snapshot {
+        Snapshot {
+            tick: self.tick,
+            server_time_ms,
+            positions: self.positions(),
+            ships: self
+                .ships
+                .values()
+                .map(|ship| ship.lock().clone())
+                .collect(),
+            probes: self.probes.clone(),
+            world_scale: self.world_scale,
+            paused: self.paused,
+            frame: 0, // set by the room when it publishes the snapshot
+        }
+    }
+
+    pub fn positions(&self) -> Vec<BodyPosition> {
+        self.bodies()
+            .into_iter()
+            .map(|(body, kind, position)| BodyPosition {
+                name: body.name.clone(),
+                kind,
+                position,
+            })
+            .collect()
+    }
+}
+
+/// The spawn point, then the rings around it in the plane of the orbits, nearest first
+fn spawn_slots(center: (f64, f64, f64)) -> impl Iterator<Item = (f64, f64, f64)> {
+    let rings = (1..=SPAWN_RINGS).flat_map(move |ring| {
+        let slots = 6 * ring;
+        let radius = ring as f64 * SPAWN_SPACING;
+        (0..slots).map(move |slot| {
+            let angle = 2.0 * PI * slot as f64 / slots as f64;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+                center.2,
+            )
+        })
+    });
+    std::iter::once(center).chain(rings)
+}
+
+pub fn distance(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distance covered in a short step from the mean anomaly `mean_anomaly`
+    fn speed_at(planet: &Planet, mean_anomaly: f64) -> f64 {
+        let delta_time = 0.01;
+        let mut planet = Planet {
+            mean_anomaly,
+            ..planet.clone()
+        };
+        let (x0, y0, z0) = planet.position();
+        planet.update_position(delta_time);
+        let (x1, y1, z1) = planet.position();
+        ((x1 - x0).powi(2) + (y1 - y0).powi(2) + (z1 - z0).powi(2)).sqrt() / delta_time
+    }
+
+    #[test]
+    fn eccentric_planet_is_faster_at_perihelion() {
+        let planet = Planet::new("Comet", 100.0, 0.5, 0.0, 60.0, 1.0, 1.0);
+        assert!(speed_at(&planet, 0.0) > speed_at(&planet, PI));
+    }
+
+    #[test]
+    fn circular_orbit_is_unchanged() {
+        let mut planet = Planet::new("Earth", 90.0, 0.0, 0.0, 60.0, 200.0, 4.0);
+        for _ in 0..10 {
+            planet.update_position(1.7);
+            let angle = planet.mean_anomaly;
+            let (x, y, z) = planet.position();
+            assert!((x - 90.0 * angle.cos()).abs() < 1e-9);
+            assert!((y - 90.0 * angle.sin()).abs() < 1e-9);
+            assert_eq!(z, 0.0);
+        }
+    }
+
+    #[test]
+    fn moon_follows_its_planet() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        for _ in 0..100 {
+            solar_system.advance(0.37);
+            let positions = solar_system.positions();
+            let earth = positions.iter().find(|body| body.name == "Earth").unwrap();
+            let moon = positions.iter().find(|body| body.name == "Moon").unwrap();
+            assert_eq!(earth.kind, BodyKind::Planet);
+            assert_eq!(moon.kind, BodyKind::Moon);
+
+            // Within the apoapsis of its orbit around the Earth, wherever the Earth is
+            let distance = ((moon.position.0 - earth.position.0).powi(2)
+                + (moon.position.1 - earth.position.1).powi(2)
+                + (moon.position.2 - earth.position.2).powi(2))
+            .sqrt();
+            assert!(distance <= 12.0 * (1.0 + 0.055) + 1e-9, "{}", distance);
+            assert!(distance >= 12.0 * (1.0 - 0.055) - 1e-9, "{}", distance);
+        }
+    }
+
+    #[test]
+    fn inclined_orbit_leaves_the_plane() {
+        let mut planet = Planet::new("Mercury", 50.0, 0.0, 7.0, 60.0, 20.0, 2.0);
+        planet.update_position(15.0); // a quarter of the orbit
+        let (_, y, z) = planet.position();
+
+        assert!(z > 0.0);
+        assert!((z / y - 7.0_f64.to_radians().tan()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn colliding_ships_separate() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut first = TheShip::new_with_uuid(Uuid::new_v4());
+        first.position = (-1.0, 0.0, 450.0);
+        first.speed = (5.0, 0.0, 0.0);
+        let mut second = TheShip::new_with_uuid(Uuid::new_v4());
+        second.position = (1.0, 0.0, 450.0);
+        second.speed = (-5.0, 0.0, 0.0);
+        let first = Arc::new(Mutex::new(first));
+        let second = Arc::new(Mutex::new(second));
+        solar_system.add_ship(first.clone());
+        solar_system.add_ship(second.clone());
+
+        solar_system.advance(1.0 / 30.0);
+
+        let first = first.lock();
+        let second = second.lock();
+        assert!(first.distance_to(second.position) >= 2.0 * TheShip::COLLISION_RADIUS - 1e-9);
+        assert!(second.speed.0 > first.speed.0);
+    }
+
+    fn body_position(solar_system: &SolarSystem, name: &str) -> (f64, f64, f64) {
+        let positions = solar_system.positions();
+        positions
+            .iter()
+            .find(|body| body.name == name)
+            .unwrap()
+            .position
+    }
+
+    #[test]
+    fn slow_ship_lands_on_earth_and_follows_it() {
+        let delta_time = 1.0 / 30.0;
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let before = body_position(&solar_system, "Earth");
+        solar_system.advance(delta_time);
+        let earth = body_position(&solar_system, "Earth");
+
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        ship.position = (earth.0 + 5.0, earth.1, earth.2);
+        ship.speed = (
+            (earth.0 - before.0) / delta_time,
+            (earth.1 - before.1) / delta_time,
+            (earth.2 - before.2) / delta_time,
+        );
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        solar_system.advance(delta_time);
+        assert_eq!(ship.lock().landed_on.as_deref(), Some("Earth"));
+
+        for _ in 0..30 {
+            solar_system.advance(delta_time);
+        }
+        let ship = ship.lock();
+        assert_eq!(ship.landed_on.as_deref(), Some("Earth"));
+        assert!(ship.distance_to(body_position(&solar_system, "Earth")) < 4.0 + LANDING_ALTITUDE);
+    }
+
+    #[test]
+    fn autopilot_matches_the_velocity_of_its_target() {
+        let delta_time = 1.0 / 30.0;
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let jupiter = body_position(&solar_system, "Jupiter");
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        ship.position = (jupiter.0, jupiter.1, jupiter.2 + 100.0);
+        ship.engage_autopilot("Jupiter");
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        let mut relative_speed = || {
+            let before = body_position(&solar_system, "Jupiter");
+            solar_system.advance(delta_time);
+            let after = body_position(&solar_system, "Jupiter");
+            let velocity = (
+                (after.0 - before.0) / delta_time,
+                (after.1 - before.1) / delta_time,
+                (after.2 - before.2) / delta_time,
+            );
+            ship.lock().speed_relative_to(velocity)
+        };
+
+        let start = relative_speed();
+        let mut previous = start;
+        for _ in 0..10 {
+            let mut speed = previous;
+            for _ in 0..10 {
+                speed = relative_speed();
+            }
+            assert!(speed <= previous.max(0.05), "{} after {}", speed, previous);
+            previous = speed;
+        }
+        assert!(previous < 0.05 * start, "{} from {}", previous, start);
+        assert_eq!(ship.lock().autopilot.as_deref(), Some("Jupiter"));
+    }
+
+    #[test]
+    fn snapshot_is_a_consistent_copy_of_one_tick() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let ship = Arc::new(Mutex::new(TheShip::new_with_uuid(Uuid::new_v4())));
+        solar_system.add_ship(ship.clone());
+        solar_system.advance(1.0 / 30.0);
+
+        let snapshot = solar_system.snapshot(0);
+
+        assert_eq!(snapshot.tick, 1);
+        let positions: Vec<_> = snapshot
+            .positions
+            .iter()
+            .map(|body| body.position)
+            .collect();
+        let current: Vec<_> = solar_system
+            .positions()
+            .iter()
+            .map(|body| body.position)
+            .collect();
+        assert_eq!(positions, current);
+        assert_eq!(snapshot.ships.len(), 1);
+        assert_eq!(snapshot.ships[0].position, ship.lock().position);
+
+        // The next tick moves the world, not the snapshot of the previous one
+        solar_system.advance(1.0 / 30.0);
+        let moved: Vec<_> = solar_system
+            .positions()
+            .iter()
+            .map(|body| body.position)
+            .collect();
+        assert_ne!(positions, moved);
+        assert_ne!(snapshot.ships[0].position, ship.lock().position);
+    }
+
+    #[test]
+    fn seeded_world_ends_at_the_same_place() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).with_seed(42);
+        let mut ship = solar_system.new_ship();
+        ship.engines.front = true;
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        for _ in 0..100 {
+            solar_system.advance(1.0 / 30.0);
+        }
+
+        assert_eq!(solar_system.snapshot(0).tick, 100);
+        let ship = ship.lock();
+        assert_eq!(
+            ship.uuid.to_string(),
+            "bdd73226-2feb-4e95-a8ef-e333b266f103"
+        );
+        assert_eq!(
+            ship.position,
+            (
+                0.013326227832681552,
+                0.0004254799693394101,
+                454.54368314229555
+            )
+        );
+    }
+
+    #[test]
+    fn ship_flying_into_earth_stays_above_the_surface() {
+        let earth_radius = 4.0;
+
+        for response in [SurfaceCollision::Stop, SurfaceCollision::Bounce] {
+            let mut solar_system =
+                SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).with_surface_collision(response);
+            let earth = body_position(&solar_system, "Earth");
+            let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+            ship.position = (earth.0 + 30.0, earth.1, earth.2);
+            ship.speed = (-40.0, 0.0, 0.0);
+            let ship = Arc::new(Mutex::new(ship));
+            solar_system.add_ship(ship.clone());
+
+            for _ in 0..60 {
+                solar_system.advance(1.0 / 30.0);
+                let earth = body_position(&solar_system, "Earth");
+                let distance = ship.lock().distance_to(earth);
+                assert!(
+                    distance >= earth_radius - 1e-9,
+                    "{:?}: {}",
+                    response,
+                    distance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tick_counts_the_simulation_steps() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        assert_eq!(solar_system.snapshot(0).tick, 0);
+
+        for step in 1..=5 {
+            solar_system.advance(1.0 / 30.0);
+            let snapshot = solar_system.snapshot(step * 33);
+            assert_eq!(snapshot.tick, step);
+            assert_eq!(snapshot.server_time_ms, step * 33);
+        }
+    }
+
+    #[test]
+    fn ships_added_back_to_back_spawn_apart() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut positions = Vec::new();
+        for _ in 0..3 {
+            let ship = solar_system.new_ship();
+            positions.push(ship.position);
+            solar_system.add_ship(Arc::new(Mutex::new(ship)));
+        }
+
+        assert_eq!(positions[0], TheShip::new_with_uuid(Uuid::nil()).position);
+        for (i, &a) in positions.iter().enumerate() {
+            for &b in &positions[i + 1..] {
+                assert!(
+                    distance(a, b) >= 2.0 * TheShip::COLLISION_RADIUS,
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn launched_probe_flies_along_the_nose_of_the_ship() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut ship = solar_system.new_ship();
+        let uuid = ship.uuid;
+        ship.probe_requested = true;
+        let direction = ship.direction;
+        let start = ship.position;
+        solar_system.add_ship(Arc::new(Mutex::new(ship)));
+
+        solar_system.advance(1.0 / 30.0);
+        solar_system.advance(1.0 / 30.0);
+        let snapshot = solar_system.snapshot(0);
+        assert_eq!(snapshot.probes.len(), 1);
+        let probe = &snapshot.probes[0];
+        assert_eq!(probe.owner, uuid);
+        let travelled = (
+            probe.position.0 - start.0,
+            probe.position.1 - start.1,
+            probe.position.2 - start.2,
+        );
+        let along =
+            travelled.0 * direction.0 + travelled.1 * direction.1 + travelled.2 * direction.2;
+        assert!(
+            along > 0.9 * distance(probe.position, start),
+            "{:?}",
+            travelled
+        );
+
+        solar_system.remove_ship(uuid);
+        assert!(solar_system.snapshot(0).probes.is_empty());
+    }
+
+    #[test]
+    fn ship_inserted_into_orbit_stays_at_its_radius() {
+        let delta_time = 1.0 / 30.0;
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let jupiter = body_position(&solar_system, "Jupiter");
+        let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+        ship.position = (jupiter.0 + 30.0, jupiter.1, jupiter.2);
+        ship.orbit_request = Some(("Jupiter".to_string(), 20.0));
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        for _ in 0..900 {
+            solar_system.advance(delta_time);
+            let ship = ship.lock();
+            let radius = ship.distance_to(body_position(&solar_system, "Jupiter"));
+            assert!((radius - 20.0).abs() < 1.0, "{}", radius);
+        }
+        assert!(ship.lock().orbit.is_some());
+    }
+
+    #[test]
+    fn prev_position_is_the_position_before_the_step() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut ship = solar_system.new_ship();
+        ship.speed = (1.0, 2.0, 0.0);
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        let before = ship.lock().position;
+        solar_system.advance(1.0 / 30.0);
+        let ship = ship.lock();
+        assert_eq!(ship.prev_position, before);
+        assert_ne!(ship.position, before);
+        assert_eq!(ship.to_json()["prev_position"], serde_json::json!(before));
+    }
+
+    #[test]
+    fn trail_keeps_only_the_latest_positions() {
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let mut ship = solar_system.new_ship();
+        ship.trail_length = 5;
+        ship.speed = (1.0, 0.0, 0.0);
+        let ship = Arc::new(Mutex::new(ship));
+        solar_system.add_ship(ship.clone());
+
+        let mut positions = Vec::new();
+        for _ in 0..20 {
+            solar_system.advance(1.0 / 30.0);
+            positions.push(ship.lock().position);
+        }
+        let ship = ship.lock();
+        assert_eq!(ship.trail.len(), 5);
+        assert!(ship.trail.iter().eq(&positions[15..]));
+        assert_eq!(ship.to_json()["trail"].as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn ship_at_rest_far_from_the_planets_falls_toward_the_sun() {
+        let speed_after_one_step = |sun_mass: f64| {
+            let mut solar_system =
+                SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).with_sun_mass(sun_mass);
+            let mut ship = TheShip::new_with_uuid(Uuid::new_v4());
+            ship.position = (0.0, 0.0, 600.0);
+            ship.speed = (0.0, 0.0, 0.0);
+            let ship = Arc::new(Mutex::new(ship));
+            solar_system.add_ship(ship.clone());
+            solar_system.advance(1.0 / 30.0);
+            let speed = ship.lock().speed;
+            speed
+        };
+
+        let speed = speed_after_one_step(DEFAULT_SUN_MASS);
+        assert!(speed.2 < 0.0, "{:?}", speed);
+        // The planets pull a little sideways, the sun only toward the origin
+        let without_sun = speed_after_one_step(0.0);
+        let pull_of_the_sun = (
+            speed.0 - without_sun.0,
+            speed.1 - without_sun.1,
+            speed.2 - without_sun.2,
+        );
+        assert!(pull_of_the_sun.2 < 0.0);
+        assert!(pull_of_the_sun.0.abs() < 1e-9 && pull_of_the_sun.1.abs() < 1e-9);
+        assert!(without_sun.2.abs() < pull_of_the_sun.2.abs());
+    }
+
+    #[test]
+    fn n_body_planet_on_a_circular_orbit_stays_circular() {
+        let delta_time = 1.0 / 30.0;
+        let planet = Planet::new("Solo", 100.0, 0.0, 0.0, 60.0, 1.0, 2.0);
+        let mut solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT)
+            .with_planets(vec![planet])
+            .with_planet_motion(PlanetMotion::NBody);
+
+        // Energy per unit of mass, the sun being by far the heaviest
+        let energy = |before: (f64, f64, f64), after: (f64, f64, f64)| {
+            let speed = distance(before, after) / delta_time;
+            let radius = distance(after, (0.0, 0.0, 0.0));
+            speed.powi(2) / 2.0 - DEFAULT_GRAVITATIONAL_CONSTANT * DEFAULT_SUN_MASS / radius
+        };
+        let mut position = body_position(&solar_system, "Solo");
+        solar_system.advance(delta_time);
+        let mut next = body_position(&solar_system, "Solo");
+        let start = energy(position, next);
+
+        // About two revolutions
+        for _ in 0..12_000 {
+            position = next;
+            solar_system.advance(delta_time);
+            next = body_position(&solar_system, "Solo");
+            let radius = distance(next, (0.0, 0.0, 0.0));
+            assert!((radius - 100.0).abs() < 2.0, "{}", radius);
+        }
+        let end = energy(position, next);
+        assert!(
+            ((end - start) / start).abs() < 0.01,
+            "{} then {}",
+            start,
+            end
+        );
+    }
+
+    /// Semi-major axis of every planet and moon, moons after their planet
+    fn orbital_radii(planets: &[Planet]) -> Vec<f64> {
+        planets
+            .iter()
+            .flat_map(|planet| {
+                std::iter::once(planet.semi_major_axis).chain(orbital_radii(&planet.moons))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn doubling_the_world_scale_doubles_the_orbital_radii() {
+        let world = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let doubled = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT).with_world_scale(2.0);
+
+        let radii = orbital_radii(&world.planets);
+        assert!(!radii.is_empty());
+        for (radius, doubled_radius) in radii.iter().zip(orbital_radii(&doubled.planets)) {
+            assert!((doubled_radius - 2.0 * radius).abs() < 1e-9);
+        }
+        // Same proportions, each body twice as far from the sun
+        for (body, doubled_body) in world.positions().iter().zip(doubled.positions()) {
+            assert_eq!(body.name, doubled_body.name);
+            let expected = (
+                2.0 * body.position.0,
+                2.0 * body.position.1,
+                2.0 * body.position.2,
+            );
+            assert!(distance(doubled_body.position, expected) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn paused_world_keeps_its_planets_where_they_are() {
+        let mut world = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        world.set_paused(true);
+        let before = world.positions();
+        for _ in 0..30 {
+            world.advance(1.0 / 30.0);
+        }
+
+        for (body, after) in before.iter().zip(world.positions()) {
+            assert_eq!(body.position, after.position, "{} moved", body.name);
+        }
+        assert_eq!(world.snapshot(0).tick, 0);
+
+        world.set_paused(false);
+        world.advance(1.0 / 30.0);
+        assert_ne!(before[0].position, world.positions()[0].position);
+    }
+
+    #[test]
+    fn single_step_advances_the_paused_world_by_one_step() {
+        let step = 1.0 / 30.0;
+        let mut stepped = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        stepped.set_paused(true);
+        assert!(stepped.request_step());
+        // Several steps to catch up, only the first one runs
+        for _ in 0..3 {
+            stepped.advance(step);
+        }
+        let mut running = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        running.advance(step);
+
+        assert_eq!(stepped.snapshot(0).tick, 1);
+        for (body, expected) in stepped.positions().iter().zip(running.positions()) {
+            assert_eq!(body.position, expected.position, "{}", body.name);
+        }
+
+        let mut running = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        assert!(!running.request_step(), "only while paused");
+    }
+}
@@ -0,0 +1,64 @@
+/// Share of a step the accumulator may miss and still run it, so that a tick waking up
+/// a little early does not leave the simulation one step behind
+const STEP_TOLERANCE: f64 = 0.1;
+
+/// Turns the wall-clock time elapsed between two ticks into a whole number of steps of
+/// the same duration, the remainder being carried over to the next tick
+pub struct FixedTimestep {
+    step: f64,
+    max_steps: u32,
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f64, max_steps: u32) -> Self {
+        Self {
+            step,
+            max_steps,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Seconds simulated by every step
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Steps to run for `elapsed` seconds. There are never more than `max_steps`: after a
+    /// long stall the time which could not be caught up is dropped
+    pub fn steps(&mut self, elapsed: f64) -> u32 {
+        self.accumulator += elapsed;
+        let steps = (self.accumulator / self.step + STEP_TOLERANCE).floor();
+
+        if steps > self.max_steps as f64 {
+            self.accumulator = 0.0;
+            return self.max_steps;
+        }
+        self.accumulator -= steps * self.step;
+        steps as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_delta_runs_several_steps() {
+        let mut timestep = FixedTimestep::new(0.1, 10);
+
+        assert_eq!(timestep.steps(0.35), 3);
+        // The remaining 0.05 s add up with the next tick
+        assert_eq!(timestep.steps(0.05), 1);
+        assert_eq!(timestep.steps(0.02), 0);
+    }
+
+    #[test]
+    fn catch_up_is_capped_after_a_stall() {
+        let mut timestep = FixedTimestep::new(0.1, 10);
+
+        assert_eq!(timestep.steps(60.0), 10);
+        // The time beyond the cap is dropped, not caught up later
+        assert_eq!(timestep.steps(0.1), 1);
+    }
+}
@@ -0,0 +1,167 @@
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How the vectors are written in the frames and the Kafka payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorFormat {
+    /// `[x, y, z]`, the legacy format
+    Array,
+    /// `{"x": .., "y": .., "z": ..}`
+    Object,
+}
+
+impl VectorFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "array" => Some(VectorFormat::Array),
+            "object" => Some(VectorFormat::Object),
+            _ => None,
+        }
+    }
+}
+
+// Serde gives no way to pass the format down to the serializer, so it is set once at startup
+static OBJECT_FORMAT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Set by `with_format`, for the serializations of this thread only
+    static FORMAT_OVERRIDE: Cell<Option<VectorFormat>> = const { Cell::new(None) };
+}
+
+/// Before anything is serialized
+pub fn set_format(format: VectorFormat) {
+    OBJECT_FORMAT.store(format == VectorFormat::Object, Ordering::Relaxed);
+}
+
+pub fn format() -> VectorFormat {
+    if let Some(format) = FORMAT_OVERRIDE.with(Cell::get) {
+        format
+    } else if OBJECT_FORMAT.load(Ordering::Relaxed) {
+        VectorFormat::Object
+    } else {
+        VectorFormat::Array
+    }
+}
+
+/// Run `serialize` with the vectors in `format`, whatever the format of the server
+#[cfg(test)]
+pub fn with_format<T>(format: VectorFormat, serialize: impl FnOnce() -> T) -> T {
+    let previous = FORMAT_OVERRIDE.with(|cell| cell.replace(Some(format)));
+    let result = serialize();
+    FORMAT_OVERRIDE.with(|cell| cell.set(previous));
+    result
+}
+
+/// A position, a speed or a direction, serialized in the `VectorFormat` of the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec3(pub f64, pub f64, pub f64);
+
+impl From<(f64, f64, f64)> for Vec3 {
+    fn from((x, y, z): (f64, f64, f64)) -> Self {
+        Vec3(x, y, z)
+    }
+}
+
+impl Vec3 {
+    fn serialize_as<S: Serializer>(
+        &self,
+        format: VectorFormat,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match format {
+            VectorFormat::Array => (self.0, self.1, self.2).serialize(serializer),
+            VectorFormat::Object => {
+                let mut vector = serializer.serialize_struct("Vec3", 3)?;
+                vector.serialize_field("x", &self.0)?;
+                vector.serialize_field("y", &self.1)?;
+                vector.serialize_field("z", &self.2)?;
+                vector.end()
+            }
+        }
+    }
+}
+
+impl Serialize for Vec3 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_as(format(), serializer)
+    }
+}
+
+/// For `#[serde(serialize_with)]` on a tuple field
+pub fn serialize<S: Serializer>(
+    vector: &(f64, f64, f64),
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Vec3::from(*vector).serialize(serializer)
+}
+
+/// Either format, whatever the one of the server, e.g. for a recording made with the other
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyFormat {
+    Array(f64, f64, f64),
+    Object { x: f64, y: f64, z: f64 },
+}
+
+/// For `#[serde(deserialize_with)]` on a tuple field
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(f64, f64, f64), D::Error> {
+    Ok(match AnyFormat::deserialize(deserializer)? {
+        AnyFormat::Array(x, y, z) | AnyFormat::Object { x, y, z } => (x, y, z),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::StatePayload;
+    use crate::ship::TheShip;
+    use crate::solar_system::{SolarSystem, DEFAULT_GRAVITATIONAL_CONSTANT};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    /// The planets of a state frame, as the clients receive them
+    fn planets(format: VectorFormat) -> (Vec<serde_json::Value>, (f64, f64, f64)) {
+        let solar_system = SolarSystem::new(DEFAULT_GRAVITATIONAL_CONSTANT);
+        let snapshot = solar_system.snapshot(0);
+        let payload = StatePayload::new(&snapshot, serde_json::Value::Null);
+        let position = solar_system.positions()[0].position;
+        let json = with_format(format, || serde_json::to_value(&payload).unwrap());
+        (json["planets"].as_array().unwrap().clone(), position)
+    }
+
+    fn ship() -> TheShip {
+        let mut ship = TheShip::new_with_uuid(Uuid::nil());
+        ship.speed = (0.25, 3.0, -4.0);
+        ship
+    }
+
+    #[test]
+    fn planet_position_as_an_object() {
+        let (planets, (x, y, z)) = planets(VectorFormat::Object);
+        assert_eq!(planets[0], json!(["Mercury", { "x": x, "y": y, "z": z }]));
+    }
+
+    #[test]
+    fn ship_speed_as_an_object() {
+        let ship = ship();
+        let speed = json!({ "x": 0.25, "y": 3.0, "z": -4.0 });
+        let json = with_format(VectorFormat::Object, || {
+            serde_json::to_value(&ship).unwrap()
+        });
+        assert_eq!(json["speed"], speed);
+        let json = with_format(VectorFormat::Object, || ship.to_json());
+        assert_eq!(json["speed"], speed);
+    }
+
+    #[test]
+    fn legacy_arrays() {
+        let (planets, (x, y, z)) = planets(VectorFormat::Array);
+        assert_eq!(planets[0], json!(["Mercury", [x, y, z]]));
+        let json = with_format(VectorFormat::Array, || ship().to_json());
+        assert_eq!(json["speed"], json!([0.25, 3.0, -4.0]));
+    }
+}